@@ -1,10 +1,14 @@
+#![allow(clippy::needless_return, clippy::single_match, clippy::len_zero)]
+
 use std::{
     thread,
     io, 
-    time::Duration};
+    str::FromStr,
+    collections::HashSet,
+    time::{Duration, Instant}};
 use tui::{
     backend::CrosstermBackend,
-    widgets::{Paragraph, Block, Borders, List, ListState, ListItem, Gauge, LineGauge},
+    widgets::{Paragraph, Block, Borders, List, ListState, ListItem, LineGauge},
     layout::{Layout, Constraint, Direction, Rect},
     style::{Style, Color, Modifier}, 
     text::Text,
@@ -12,38 +16,166 @@ use tui::{
     Terminal
 };
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
+    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers, MouseEventKind, MouseButton},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
 use crate::songdb;
 use crate::songdb::SongDB;
+use crate::songdb::loudness;
 use crate::player;
+use crate::player::{PlayerEvent, FinishReason, ReplayGain};
+use crate::player::effects::Effects;
+use crate::xspf;
+use crate::config::Config;
+use crate::keymap::{self, Action, Scope, Lookup};
 use crate::song::Song;
 use crate::app::navigator::{Navigator};
 use crate::app::songlist::{SongList, SongOrder};
-use crate::app::command::{Command,Response};
-use crate::app::songqueue::SongQueue;
+use crate::app::command::Response;
+use crate::app::songqueue::{SongQueue, Shuffle, Repeat};
 use crate::app::search::Search;
+use crate::app::settings::Settings;
+use crate::app::cmdline::{CmdLine, LineCommand};
+use crate::app::theme::Theme;
 
 mod navigator;
 mod command;
 mod songlist;
 mod songqueue;
 mod search;
+mod settings;
+mod cmdline;
+pub mod theme;
+
+// how far into a song going to the previous song restarts it instead
+const PREVIOUS_RESTART_SECS : f64 = 3.0;
+const SHORT_SEEK_SECS : f64 = 5.0;
+const LONG_SEEK_SECS : f64 = 30.0;
+const VOLUME_STEP : f64 = 5.0;
+const MAX_CROSSFADE_SECS : f64 = 12.0;
+const MAX_PREAMP_DB : f64 = 15.0;
+const SPEED_STEP : f64 = 0.1;
+// where export_queue writes, in the working directory. ":save playlist" picks its own name
+const EXPORT_PATH : &str = "queue.xspf";
+// what the sleep timer cycles through, in minutes
+const SLEEP_MINUTES : [u64; 6] = [15, 30, 45, 60, 90, 120];
+// positions this close to either end of a song aren't worth resuming from, in seconds
+const RESUME_MARGIN_SECS : f64 = 10.0;
+// how long before the sleep timer runs out the fade out starts
+const SLEEP_FADE_SECS : f64 = 60.0;
+// how far ctrl+d/ctrl+u move in vim mode
+const HALF_PAGE_ROWS : usize = 10;
+const MAX_COUNT : usize = 9999;
+// two clicks on the same entry this close together activate it
+const DOUBLE_CLICK : Duration = Duration::from_millis(400);
+// how far one step of the scroll wheel moves
+const SCROLL_ROWS : isize = 3;
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum SelectedPanel {
     SongList,
     Nav,
     Queue,
     Search,
+    Settings,
+}
+
+// where things were drawn last, to find what the mouse is on
+#[derive(Default)]
+struct Areas {
+    nav: Rect,
+    songs: Rect,
+    queue: Rect,
+    settings: Rect,
+    search: Rect,
+    gauge: Option<Rect>, // the line of the progress gauge, while a song plays
+    offsets: [usize; 4], // first entry shown in the nav, songs, queue and settings lists
+    lens: [usize; 4], // and how many entries they have
+}
+
+impl Areas {
+    // the panel at a position, and the entry of its list there if it's on one
+    fn panel_at(&self, column: u16, row: u16) -> Option<(SelectedPanel, Option<usize>)> {
+        let panels = [
+            (SelectedPanel::Nav, self.nav),
+            (SelectedPanel::SongList, self.songs),
+            (SelectedPanel::Queue, self.queue),
+            (SelectedPanel::Settings, self.settings),
+            (SelectedPanel::Search, self.search),
+        ];
+        for (i, (panel, rect)) in panels.iter().enumerate() {
+            if column < rect.x || column >= rect.right() || row < rect.y || row >= rect.bottom() {
+                continue;
+            }
+            // inside the borders
+            let entry = if i < self.offsets.len() && row > rect.y && row + 1 < rect.bottom() {
+                Some(self.offsets[i] + (row - rect.y - 1) as usize).filter(|e| *e < self.lens[i])
+            } else {
+                None
+            };
+            return Some((*panel, entry));
+        }
+        return None;
+    }
+}
+
+// what a speed change is remembered for
+#[derive(Clone, Copy, PartialEq)]
+enum SpeedMemory {
+    Off, // every song plays at the same speed
+    Song,
+    Album,
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for SpeedMemory {
+    fn to_string(&self) -> String {
+        return match self {
+            SpeedMemory::Off   => String::from("off"),
+            SpeedMemory::Song  => String::from("song"),
+            SpeedMemory::Album => String::from("album"),
+        }
+    }
+}
+
+impl FromStr for SpeedMemory {
+    type Err = ();
+    fn from_str(s: &str) -> Result<SpeedMemory, ()> {
+        return match s {
+            "off"   => Ok(SpeedMemory::Off),
+            "song"  => Ok(SpeedMemory::Song),
+            "album" => Ok(SpeedMemory::Album),
+            _ => Err(()),
+        }
+    }
+}
+
+// when playback stops by itself
+#[derive(Clone, Copy, PartialEq)]
+enum StopAfter {
+    Off,
+    Track,
+    Album, // once the next song is from another album
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for StopAfter {
+    fn to_string(&self) -> String {
+        return match self {
+            StopAfter::Off   => String::from("off"),
+            StopAfter::Track => String::from("track"),
+            StopAfter::Album => String::from("album"),
+        }
+    }
 }
 
 pub struct App {
     songs: SongDB,
-    player: player::Player
+    player: player::Player,
+    config: Config,
+    playlist: Option<String>,
 }
 
 impl App {
@@ -65,6 +197,61 @@ impl App {
         let mut songqueue : SongQueue = SongQueue::new();
         let mut songqueue_state = ListState::default();
         songqueue_state.select(None);
+        // pick up where the last session left off
+        let shuffle = self.songs.get_state("shuffle").and_then(|v| v.parse().ok()).unwrap_or(Shuffle::Off);
+        let repeat = self.songs.get_state("repeat").and_then(|v| v.parse().ok()).unwrap_or(Repeat::Consume);
+        songqueue.restore(self.songs.load_queue(), shuffle);
+        songqueue.set_repeat(repeat);
+        match self.songs.get_state("volume").and_then(|v| v.parse().ok()) {
+            Some(v) => { self.player.set_volume(v).ok(); },
+            None => {},
+        }
+        self.player.set_mute(self.songs.get_state("mute").as_deref() == Some("true")).ok();
+        match self.songs.get_state("replaygain").and_then(|v| v.parse().ok()) {
+            Some(v) => self.player.set_replaygain(v),
+            None => {},
+        }
+        match self.songs.get_state("preamp").and_then(|v| v.parse().ok()) {
+            Some(v) => self.player.set_preamp(v),
+            None => {},
+        }
+        // the speed songs without a remembered one play at
+        let mut speed : f64 = self.songs.get_state("speed").and_then(|v| v.parse().ok()).unwrap_or(1.0);
+        let mut speed_memory = self.songs.get_state("speed_memory").and_then(|v| v.parse().ok()).unwrap_or(SpeedMemory::Off);
+        self.player.set_speed(speed).ok();
+        self.player.set_pitch_correction(self.songs.get_state("pitch_correction").as_deref() != Some("false")).ok();
+        // the config file decides the output device, otherwise it's the last one picked
+        match self.config.audio_device.clone().or(self.songs.get_state("audio_device")) {
+            Some(d) => { self.player.set_device(&d).ok(); },
+            None => {},
+        }
+        self.player.refresh_devices().ok();
+        let mut crossfade : f64 = self.songs.get_state("crossfade").and_then(|v| v.parse().ok()).unwrap_or(0.0);
+        let mut offset : f64 = self.songs.get_state("offset").and_then(|v| v.parse().ok()).unwrap_or(0.0);
+        let mut last_save = Instant::now();
+        let mut stop_after = StopAfter::Off;
+        // when the sleep timer runs out, and whether it fades out before then
+        let mut sleep_at : Option<Instant> = None;
+        let mut sleep_minutes : u64 = 0;
+        let mut sleep_fade = false;
+        // the a marker of a loop that doesn't have its b yet
+        let mut loop_start : Option<f64> = None;
+
+        // shown in the command line once it's there
+        let mut import_error : Option<String> = None;
+        match &self.playlist {
+            Some(p) => {
+                match xspf::import(&self.songs, p) {
+                    Ok(songs) => {
+                        for song in songs {
+                            songqueue.push(song);
+                        }
+                    },
+                    Err(e) => { import_error = Some(format!("{}: {}", p, e)); },
+                }
+            },
+            None => {},
+        }
 
         let mut navigator : Navigator = Navigator::new();
         let mut navigator_state = ListState::default();
@@ -73,81 +260,169 @@ impl App {
         navigator.fill_category(0, &mut self.songs.get_table(songdb::Table::Album).unwrap());
         navigator.fill_category(1, &mut self.songs.get_table(songdb::Table::Artist).unwrap());
         navigator.fill_category(2, &mut self.songs.get_table(songdb::Table::Genre).unwrap());
+        navigator.set_category(3, self.songs.get_resume_titles());
+        // the song the player is on, and where it can be resumed from when it starts
+        let mut playing_song : Option<Song> = None;
+        let mut resume_offer : Option<f64> = None;
 
         let mut searchbar : Search = Search::new();
 
+        // the effects preset picked in the settings, albums and genres can have their own
+        let mut preset : String = self.songs.get_state("preset")
+            .filter(|p| self.config.presets.iter().any(|x| x.name == *p))
+            .unwrap_or(String::from("flat"));
+        // the config file decides the theme, otherwise it's the last one picked
+        let mut theme : Theme = self.config.theme.clone().or(self.songs.get_state("theme"))
+            .and_then(|t| self.config.themes.iter().find(|x| x.name == t).cloned())
+            .unwrap_or(self.config.themes[0].clone());
+        let mut settings : Settings = Settings::new(self.config.presets.iter().map(|p| p.name.clone()).collect());
+        let mut settings_state = ListState::default();
+
+        // loudness analysis running in the background, and how far along it is
+        let mut analysis : Option<std::sync::mpsc::Receiver<loudness::Update>> = None;
+        let mut analysis_progress : (usize, usize) = (0, 0);
+
         let mut panel = SelectedPanel::SongList;
 
+        // vim mode: keys of a binding like "g g" typed so far, and the count typed before them
+        let mut pending_keys : Vec<keymap::Chord> = Vec::new();
+        let mut typed_count : usize = 0;
+        let mut cmdline : CmdLine = CmdLine::new();
+        cmdline.message = import_error;
+        let mut last_find : Option<String> = None;
+
+        let mut areas = Areas::default();
+        // the last click on an entry, for double clicks, and the queue entry being dragged
+        let mut last_click : Option<(SelectedPanel, usize, Instant)> = None;
+        let mut dragging = false;
+
         loop {
             // read input
-            let curr_panel : &mut dyn command::Command = match panel {
-                SelectedPanel::SongList => &mut songlist,
-                SelectedPanel::Queue => &mut songqueue,
-                SelectedPanel::Nav => &mut navigator,
-                SelectedPanel::Search => &mut searchbar,
-                _ => &mut songlist,
-            };
-
             let mut response : Option<Response> = None;
+            let mut action : Option<Action> = None;
+            let mut typed : Option<char> = None;
+            let mut line : Option<Result<LineCommand, String>> = None;
+            let mut count : usize = 0;
             match crossterm::event::poll(Duration::new(0,10000)) {
                 Ok(true) => {
                     match crossterm::event::read()? {
                         Event::Key(event) => {
-                            match event.modifiers {
-                                KeyModifiers::CONTROL => {
-                                    match event.code {
-                                        KeyCode::Char('p') => {
-                                            self.player.stop().ok();
-                                            let song = songqueue.queue.get(0);
-                                            match song {
-                                                Some(x) => { 
-                                                    self.player.play(&x.path[..]).ok(); 
-                                                    songqueue.set_currently_playing(0);
+                            let chord = keymap::chord(&event);
+                            if cmdline.is_open() {
+                                line = cmdline.key(event.code);
+                            }
+                            else {
+                                cmdline.message = None;
+                                let scope = match panel {
+                                    SelectedPanel::SongList => Scope::Songs,
+                                    SelectedPanel::Queue => Scope::Queue,
+                                    SelectedPanel::Nav => Scope::Navigator,
+                                    SelectedPanel::Search => Scope::Search,
+                                    SelectedPanel::Settings => Scope::Settings,
+                                };
+                                match chord {
+                                    // in vim mode digits before a key are a count, 5j goes down 5
+                                    (KeyModifiers::NONE, KeyCode::Char(c)) if self.config.vim_mode && scope != Scope::Search
+                                        && pending_keys.len() == 0 && c.is_ascii_digit() && (c != '0' || typed_count > 0) => {
+                                        typed_count = (typed_count * 10 + c.to_digit(10).unwrap() as usize).min(MAX_COUNT);
+                                    },
+                                    _ => {
+                                        pending_keys.push(chord);
+                                        match self.config.keymap.get(scope, &pending_keys) {
+                                            Lookup::Prefix => {}, // wait for the rest
+                                            Lookup::Action(a) => {
+                                                action = Some(a);
+                                                count = typed_count;
+                                                pending_keys.clear();
+                                                typed_count = 0;
+                                            },
+                                            Lookup::Unbound => {
+                                                // keys that aren't bound are typed into the panel
+                                                match (pending_keys.len(), chord) {
+                                                    (1, (KeyModifiers::NONE, KeyCode::Char(c))) => { typed = Some(c); },
+                                                    _ => {},
+                                                }
+                                                pending_keys.clear();
+                                                typed_count = 0;
+                                            },
+                                        }
+                                    },
+                                }
+                            }
+                        },
+                        Event::Mouse(event) => {
+                            let at = areas.panel_at(event.column, event.row);
+                            match event.kind {
+                                MouseEventKind::Down(MouseButton::Left) => {
+                                    match (areas.gauge, at) {
+                                        // seek to where the gauge was clicked
+                                        (Some(g), _) if event.row == g.y && event.column >= g.x && event.column < g.right() => {
+                                            let fraction = (event.column - g.x) as f64 / g.width as f64;
+                                            self.player.seek_to(self.player.get_song_duration() * fraction).ok();
+                                        },
+                                        (_, Some((clicked, entry))) => {
+                                            if clicked == SelectedPanel::Settings && panel != clicked {
+                                                self.player.refresh_devices().ok();
+                                            }
+                                            panel = clicked;
+                                            match entry {
+                                                Some(i) => {
+                                                    let target : &mut dyn command::Command = match clicked {
+                                                        SelectedPanel::Nav => {
+                                                            navigator.select_row(i);
+                                                            &mut navigator
+                                                        },
+                                                        SelectedPanel::Queue => &mut songqueue,
+                                                        SelectedPanel::Settings => &mut settings,
+                                                        _ => &mut songlist,
+                                                    };
+                                                    if clicked != SelectedPanel::Nav {
+                                                        target.command(&command::Event::Select(i));
+                                                    }
+                                                    let double = match last_click {
+                                                        Some((p, e, t)) => p == clicked && e == i && t.elapsed() < DOUBLE_CLICK,
+                                                        None => false,
+                                                    };
+                                                    if double {
+                                                        response = target.command(&command::Event::Accept);
+                                                        last_click = None;
+                                                    }
+                                                    else {
+                                                        last_click = Some((clicked, i, Instant::now()));
+                                                    }
+                                                    dragging = clicked == SelectedPanel::Queue && songqueue.get_selection() == Some(i as u32);
                                                 },
-                                                _ => {}
+                                                None => {},
                                             }
                                         },
-                                        KeyCode::Char('q') => {
-                                            panel = SelectedPanel::Queue;
-                                        },
-                                        KeyCode::Char('w') => {
-                                            panel = SelectedPanel::SongList;
-                                        },
-                                        KeyCode::Char('s') => {
-                                            panel = SelectedPanel::Search;
-                                        }
-                                        _ => {}
+                                        _ => {},
                                     }
                                 },
-                                KeyModifiers::NONE => {
-                                    // command 
-                                    let command : command::Event = match event.code {
-                                        KeyCode::Esc => {break;}, // breaks out of loop
-                                        KeyCode::Up => command::Event::Up,
-                                        KeyCode::Down => command::Event::Down,
-                                        KeyCode::Enter => command::Event::Accept,
-                                        KeyCode::Left => command::Event::Left,
-                                        KeyCode::Right => command::Event::Right,
-                                        KeyCode::Backspace => command::Event::Back,
-                                        KeyCode::Char(c) => command::Event::Char(c),
-                                        _ => command::Event::Nothing, // else do nothing
+                                MouseEventKind::Drag(MouseButton::Left) if dragging => {
+                                    match at {
+                                        Some((SelectedPanel::Queue, Some(i))) => songqueue.move_selected(i),
+                                        _ => {},
+                                    }
+                                },
+                                MouseEventKind::Up(MouseButton::Left) => { dragging = false; },
+                                // the wheel moves the list it's over, focused or not
+                                MouseEventKind::ScrollDown | MouseEventKind::ScrollUp => {
+                                    let rows = if event.kind == MouseEventKind::ScrollUp { -SCROLL_ROWS } else { SCROLL_ROWS };
+                                    let target : Option<&mut dyn command::Command> = match at {
+                                        Some((SelectedPanel::Nav, _)) => Some(&mut navigator),
+                                        Some((SelectedPanel::SongList, _)) => Some(&mut songlist),
+                                        Some((SelectedPanel::Queue, _)) => Some(&mut songqueue),
+                                        Some((SelectedPanel::Settings, _)) => Some(&mut settings),
+                                        _ => None,
                                     };
-
-                                    response = curr_panel.command(&command);
-
-                                    match event.code {
-                                        KeyCode::Tab => {
-                                            panel = SelectedPanel::Nav;
-                                        },
-                                        _ => {}
+                                    match target {
+                                        Some(t) => { t.command(&command::Event::Move(rows)); },
+                                        None => {},
                                     }
-                                }
-                                _ => {}
+                                },
+                                _ => {},
                             }
-
-                            // println!("{:?}", event);
                         },
-                        // Event::Mouse(event) => println!("{:?}", event),
                         // Event::Resize(width, height) => println!("New size {}x{}", width, height),
                         // Event::Paste(data) => println!("{:?}", data),
                         _ => {}, // else do nothing else
@@ -156,6 +431,349 @@ impl App {
                 _ => {},
             }
 
+            // what was typed into the command line
+            match line {
+                Some(Ok(LineCommand::Action(a))) => { action = Some(a); },
+                Some(Ok(LineCommand::Find(f))) => {
+                    last_find = Some(f);
+                    action = Some(Action::FindNext);
+                },
+                Some(Ok(LineCommand::Add(all))) => {
+                    let songs = if all { songlist.get_items() } else { songlist.get_selected_song().into_iter().collect() };
+                    for song in songs {
+                        songqueue.push(song);
+                    }
+                },
+                Some(Ok(LineCommand::Shuffle(shuffle))) => {
+                    match shuffle {
+                        Some(s) => songqueue.set_shuffle(s),
+                        None => songqueue.shuffle_remaining(),
+                    }
+                },
+                Some(Ok(LineCommand::Repeat(repeat))) => {
+                    match repeat {
+                        Some(r) => songqueue.set_repeat(r),
+                        None => songqueue.cycle_repeat(),
+                    }
+                },
+                Some(Ok(LineCommand::SavePlaylist(name))) => {
+                    let path = if name.ends_with(".xspf") { name } else { format!("{}.xspf", name) };
+                    let songs : Vec<Song> = songqueue.queue.iter().cloned().collect();
+                    match xspf::export(&path, &songs) {
+                        Ok(_) => { cmdline.message = Some(format!("saved {}", path)); },
+                        Err(e) => { cmdline.message = Some(format!("{}: {}", path, e)); },
+                    }
+                },
+                Some(Ok(LineCommand::Seek(seconds, relative))) => {
+                    if relative {
+                        self.player.seek(seconds).ok();
+                    }
+                    else {
+                        self.player.seek_to(seconds).ok();
+                    }
+                },
+                Some(Ok(LineCommand::Volume(volume, relative))) => {
+                    if relative {
+                        self.player.change_volume(volume).ok();
+                    }
+                    else {
+                        self.player.set_volume(volume).ok();
+                    }
+                },
+                Some(Err(e)) => { cmdline.message = Some(e); },
+                None => {},
+            }
+
+            let curr_panel : &mut dyn command::Command = match panel {
+                SelectedPanel::SongList => &mut songlist,
+                SelectedPanel::Queue => &mut songqueue,
+                SelectedPanel::Nav => &mut navigator,
+                SelectedPanel::Search => &mut searchbar,
+                SelectedPanel::Settings => &mut settings,
+            };
+            match typed {
+                Some(c) => { response = curr_panel.command(&command::Event::Char(c)); },
+                None => {},
+            }
+            match action {
+                Some(Action::Quit) => {break;}, // breaks out of loop
+                Some(Action::Play) => {
+                    self.player.stop().ok();
+                    match songqueue.get_first() {
+                        Some(i) => { 
+                            self.player.play(&songqueue.queue[i as usize].path[..]).ok(); 
+                            songqueue.set_currently_playing(i);
+                        },
+                        _ => {}
+                    }
+                },
+                Some(Action::FocusQueue) => {
+                    panel = SelectedPanel::Queue;
+                },
+                Some(Action::FocusSongs) => {
+                    panel = SelectedPanel::SongList;
+                },
+                Some(Action::FocusSearch) => {
+                    panel = SelectedPanel::Search;
+                }
+                Some(Action::FocusSettings) => {
+                    panel = SelectedPanel::Settings;
+                    self.player.refresh_devices().ok();
+                }
+                Some(Action::FocusNavigator) => {
+                    panel = SelectedPanel::Nav;
+                },
+                Some(Action::TogglePause) => {
+                    self.player.toggle_pause().ok();
+                }
+                Some(Action::SeekBack) => {
+                    self.player.seek(-SHORT_SEEK_SECS).ok();
+                }
+                Some(Action::SeekForward) => {
+                    self.player.seek(SHORT_SEEK_SECS).ok();
+                }
+                Some(Action::VolumeUp) => {
+                    self.player.change_volume(VOLUME_STEP).ok();
+                }
+                Some(Action::VolumeDown) => {
+                    self.player.change_volume(-VOLUME_STEP).ok();
+                }
+                Some(Action::Previous) => {
+                    // go back to the start of the song, or to the song
+                    // before it if it just started
+                    if self.player.get_time_pos() > PREVIOUS_RESTART_SECS {
+                        self.player.seek_to(0.0).ok();
+                    }
+                    else {
+                        match songqueue.previous() {
+                            Some(s) => {
+                                self.player.stop().ok();
+                                self.player.play(&s.path[..]).ok();
+                            },
+                            None => { self.player.seek_to(0.0).ok(); },
+                        }
+                    }
+                }
+                Some(Action::ResumeSession) => {
+                    // resume the song from the last session where it stopped
+                    let current = songqueue.get_currently_playing().or(songqueue.get_first());
+                    match current {
+                        Some(i) => {
+                            self.player.play_from(&songqueue.queue[i as usize].path[..], offset).ok();
+                            songqueue.set_currently_playing(i);
+                        },
+                        None => {},
+                    }
+                }
+                Some(Action::ExportQueue) => {
+                    let songs : Vec<Song> = songqueue.queue.iter().cloned().collect();
+                    cmdline.message = match xspf::export(EXPORT_PATH, &songs) {
+                        Ok(_) => Some(format!("saved {}", EXPORT_PATH)),
+                        Err(e) => Some(format!("{}: {}", EXPORT_PATH, e)),
+                    };
+                }
+                Some(a @ (Action::Analyze | Action::AnalyzeAndTag)) if analysis.is_none() => {
+                    // measure every album that has songs without replaygain,
+                    // analyze_and_tag also writes the tags into the files
+                    let songs = self.songs.search_all();
+                    let untagged : HashSet<(String,String)> = songs.iter()
+                        .filter(|s| s.track_gain.is_none())
+                        .map(|s| (s.album_artist.clone(), s.album.clone()))
+                        .collect();
+                    let songs : Vec<Song> = songs.into_iter()
+                        .filter(|s| untagged.contains(&(s.album_artist.clone(), s.album.clone())))
+                        .collect();
+                    if songs.len() > 0 {
+                        let total = songs.len();
+                        match loudness::spawn(songs, a == Action::AnalyzeAndTag) {
+                            Ok(r) => {
+                                analysis_progress = (0, total);
+                                analysis = Some(r);
+                            },
+                            Err(e) => { cmdline.message = Some(e); },
+                        }
+                    }
+                }
+                Some(Action::SeekBackLong) => {
+                    self.player.seek(-LONG_SEEK_SECS).ok();
+                },
+                Some(Action::SeekForwardLong) => {
+                    self.player.seek(LONG_SEEK_SECS).ok();
+                },
+                Some(Action::ToggleMute) => {
+                    self.player.toggle_mute().ok();
+                },
+                Some(Action::CycleReplayGain) => {
+                    let replaygain = match self.player.get_replaygain() {
+                        ReplayGain::Off   => ReplayGain::Track,
+                        ReplayGain::Track => ReplayGain::Album,
+                        ReplayGain::Album => ReplayGain::Auto,
+                        ReplayGain::Auto  => ReplayGain::Off,
+                    };
+                    self.player.set_replaygain(replaygain);
+                },
+                Some(Action::PreampDown) => {
+                    let preamp = self.player.get_preamp() - 1.0;
+                    self.player.set_preamp(preamp.max(-MAX_PREAMP_DB));
+                },
+                Some(Action::PreampUp) => {
+                    let preamp = self.player.get_preamp() + 1.0;
+                    self.player.set_preamp(preamp.min(MAX_PREAMP_DB));
+                },
+                Some(Action::CrossfadeDown) => {
+                    crossfade = (crossfade - 1.0).max(0.0);
+                },
+                Some(Action::CrossfadeUp) => {
+                    crossfade = (crossfade + 1.0).min(MAX_CROSSFADE_SECS);
+                },
+                Some(a @ (Action::SpeedDown | Action::SpeedUp | Action::SpeedReset)) => {
+                    match a {
+                        Action::SpeedDown => { self.player.change_speed(-SPEED_STEP).ok(); },
+                        Action::SpeedUp => { self.player.change_speed(SPEED_STEP).ok(); },
+                        _ => { self.player.set_speed(1.0).ok(); },
+                    }
+                    match (speed_memory, songqueue.get_currently_playing_song()) {
+                        (SpeedMemory::Off, _) => { speed = self.player.get_speed(); },
+                        (m, Some(s)) => self.remember_speed(&s, m),
+                        _ => {},
+                    }
+                },
+                Some(Action::TogglePitchCorrection) => {
+                    self.player.toggle_pitch_correction().ok();
+                },
+                Some(Action::CycleSpeedMemory) => {
+                    speed_memory = match speed_memory {
+                        SpeedMemory::Off   => SpeedMemory::Song,
+                        SpeedMemory::Song  => SpeedMemory::Album,
+                        SpeedMemory::Album => SpeedMemory::Off,
+                    };
+                    if speed_memory == SpeedMemory::Off {
+                        self.player.set_speed(speed).ok();
+                    }
+                },
+                Some(Action::LoopStart) => {
+                    // moving a of a loop keeps it going if it's still before b
+                    let position = self.player.get_time_pos();
+                    match self.player.get_loop() {
+                        Some((_, end)) if position < end => {
+                            self.player.set_loop(Some((position, end))).ok();
+                        },
+                        _ => {
+                            self.player.set_loop(None).ok();
+                            loop_start = Some(position);
+                        },
+                    }
+                },
+                Some(Action::LoopEnd) => {
+                    let position = self.player.get_time_pos();
+                    let start = loop_start.or(self.player.get_loop().map(|l| l.0));
+                    match start {
+                        Some(s) if s < position => {
+                            self.player.set_loop(Some((s, position))).ok();
+                            loop_start = None;
+                        },
+                        _ => {},
+                    }
+                },
+                Some(Action::LoopClear) => {
+                    // forgets the saved one too
+                    self.player.set_loop(None).ok();
+                    loop_start = None;
+                    match songqueue.get_currently_playing_song() {
+                        Some(s) => { self.songs.set_loop(&s.title, &s.album, None).ok(); },
+                        None => {},
+                    }
+                },
+                Some(Action::LoopSave) => {
+                    match (songqueue.get_currently_playing_song(), self.player.get_loop()) {
+                        (Some(s), Some(l)) => { self.songs.set_loop(&s.title, &s.album, Some(l)).ok(); },
+                        _ => {},
+                    }
+                },
+                Some(Action::ResumePosition) => {
+                    match resume_offer.take() {
+                        Some(p) => { self.player.seek_to(p).ok(); },
+                        None => {},
+                    }
+                },
+                Some(Action::CycleStopAfter) => {
+                    stop_after = match stop_after {
+                        StopAfter::Off   => StopAfter::Track,
+                        StopAfter::Track => StopAfter::Album,
+                        StopAfter::Album => StopAfter::Off,
+                    };
+                },
+                Some(Action::CycleSleepTimer) => {
+                    // the timer starts over from now each time
+                    sleep_minutes = SLEEP_MINUTES.iter().copied().find(|m| *m > sleep_minutes).unwrap_or(0);
+                    sleep_at = match sleep_minutes {
+                        0 => None,
+                        m => Some(Instant::now() + Duration::from_secs(m * 60)),
+                    };
+                    self.player.set_volume_scale(1.0).ok();
+                },
+                Some(Action::ToggleSleepFade) => {
+                    sleep_fade = !sleep_fade;
+                    if !sleep_fade {
+                        self.player.set_volume_scale(1.0).ok();
+                    }
+                },
+                Some(Action::Jump(tenth)) => {
+                    // jump to 0%, 10%, ..., 90% of the song
+                    let fraction = tenth as f64 / 10.0;
+                    self.player.seek_to(self.player.get_song_duration() * fraction).ok();
+                },
+                Some(Action::CycleShuffle) => songqueue.cycle_shuffle(),
+                Some(Action::ShuffleRemaining) => songqueue.shuffle_remaining(),
+                Some(Action::CycleRepeat) => songqueue.cycle_repeat(),
+                Some(a @ (Action::Up | Action::Down | Action::Left | Action::Right | Action::Accept | Action::Back)) => {
+                    // command 
+                    let command : command::Event = match a {
+                        Action::Up => command::Event::Up,
+                        Action::Down => command::Event::Down,
+                        Action::Left => command::Event::Left,
+                        Action::Right => command::Event::Right,
+                        Action::Accept => command::Event::Accept,
+                        _ => command::Event::Back,
+                    };
+                    for _ in 0..count.max(1) {
+                        response = curr_panel.command(&command);
+                    }
+                },
+                // a count picks the entry for these, like 5gg
+                Some(Action::First) => {
+                    response = curr_panel.command(&command::Event::Select(count.saturating_sub(1)));
+                },
+                Some(Action::Last) => {
+                    let entry = if count > 0 { count - 1 } else { usize::MAX };
+                    response = curr_panel.command(&command::Event::Select(entry));
+                },
+                Some(a @ (Action::HalfPageDown | Action::HalfPageUp)) => {
+                    let rows = (HALF_PAGE_ROWS * count.max(1)) as isize;
+                    let rows = if a == Action::HalfPageUp { -rows } else { rows };
+                    response = curr_panel.command(&command::Event::Move(rows));
+                },
+                Some(Action::CycleTheme) => {
+                    let i = self.config.themes.iter().position(|t| t.name == theme.name).map(|i| i + 1).unwrap_or(0);
+                    theme = self.config.themes[i % self.config.themes.len()].clone();
+                    self.songs.set_state("theme", &theme.name).ok();
+                    cmdline.message = Some(format!("theme: {}", theme.name));
+                },
+                Some(Action::Find) => cmdline.open('/'),
+                Some(Action::CommandLine) => cmdline.open(':'),
+                Some(a @ (Action::FindNext | Action::FindPrevious)) => {
+                    match &last_find {
+                        Some(f) => {
+                            for _ in 0..count.max(1) {
+                                response = curr_panel.command(&command::Event::Find(f.clone(), a == Action::FindNext));
+                            }
+                        },
+                        None => {},
+                    }
+                },
+                _ => {}
+            }
+
             // process command
             match response {
                 Some(r) => {
@@ -178,7 +796,14 @@ impl App {
                             songlist = SongList::new(self.songs.search_any(&s));
                             songlist.order_items(SongOrder::Album);
                         }
-                        _ => {},
+                        Response::SetDevice(d) => {
+                            self.player.set_device(&d).ok();
+                            self.songs.set_state("audio_device", &d).ok();
+                        }
+                        Response::SetPreset(p) => {
+                            self.songs.set_state("preset", &p).ok();
+                            preset = p;
+                        }
                     }
                 }
                 _ => {},
@@ -199,16 +824,180 @@ impl App {
                 None => {}, 
             }
             navigator_state.select(Some(nav_selection as usize));
+            settings_state.select(settings.get_selection());
 
             // check if player is done with song, play next if there is one
-            if self.player.is_song_finished() {
-                songqueue.pop_currently_playing();
-                match songqueue.get_currently_playing_song() {
-                    Some(s) => { let _ = self.player.play(&s.path[..]); }
-                    None => {},
+            // with crossfade on, the next song starts before the current one ends. songs of the
+            // same album are left gapless
+            // in seconds of playback, which isn't the song's seconds at other speeds
+            let time_left = self.player.get_time_left() / self.player.get_speed();
+            let stopping = stops_after_current(stop_after, &songqueue);
+            if crossfade > 0.0 && !stopping && !self.player.is_crossfading()
+                && self.player.get_song_duration() > 0.0 && time_left > 0.0 && time_left <= crossfade {
+                let current = songqueue.get_currently_playing_song();
+                match (current, songqueue.get_upcoming()) {
+                    (Some(c), Some(n)) if c.album != n.album => {
+                        songqueue.advance();
+                        self.player.crossfade(&n.path[..], time_left).ok();
+                    },
+                    _ => {},
                 }
             }
 
+            for event in self.player.poll() {
+                match &event {
+                    PlayerEvent::Finished { reason: FinishReason::Eof, .. } => {
+                        // played to the end, nothing to resume
+                        match playing_song.take() {
+                            Some(s) => self.save_resume(&s, s.duration, &mut navigator),
+                            None => {},
+                        }
+                        resume_offer = None;
+                    },
+                    _ => {},
+                }
+                match event {
+                    PlayerEvent::Devices(d) => settings.set_devices(d),
+                    PlayerEvent::Error(e) => { cmdline.message = Some(format!("player: {}", e)); },
+                    // the speed went back to what it was, remember that one instead
+                    PlayerEvent::SpeedRejected { .. } => {
+                        match (speed_memory, songqueue.get_currently_playing_song()) {
+                            (SpeedMemory::Off, _) => { speed = self.player.get_speed(); },
+                            (m, Some(s)) => self.remember_speed(&s, m),
+                            _ => {},
+                        }
+                    },
+                    PlayerEvent::Unplayable(path) => {
+                        songqueue.mark_broken(&path);
+                        self.songs.flag_broken(&path).ok();
+                        // go on with the next song if it was the one that should be playing
+                        let current = songqueue.get_currently_playing_song();
+                        if current.map(|s| s.path) == Some(path) {
+                            playing_song = None;
+                            match songqueue.skip() {
+                                Some(s) => { self.player.play(&s.path[..]).ok(); },
+                                None => { self.player.stop().ok(); },
+                            }
+                        }
+                    },
+                    PlayerEvent::Started(path) => {
+                        let song = songqueue.queue.iter().find(|s| s.path == path).cloned();
+                        // offset is still where the last song was left
+                        match &playing_song {
+                            Some(s) if resume_offer.is_none() => self.save_resume(s, offset, &mut navigator),
+                            _ => {},
+                        }
+                        offset = 0.0;
+                        resume_offer = song.as_ref().and_then(|s| self.songs.get_resume(&s.title, &s.album));
+                        playing_song = song.clone();
+                        loop_start = None;
+                        match song.as_ref().and_then(|s| self.songs.get_loop(&s.title, &s.album)) {
+                            Some(l) => { self.player.set_loop(Some(l)).ok(); },
+                            None => {},
+                        }
+                        if speed_memory != SpeedMemory::Off {
+                            let song_speed = song.and_then(|s| self.remembered_speed(&s, speed_memory)).unwrap_or(speed);
+                            if song_speed != self.player.get_speed() {
+                                self.player.set_speed(song_speed).ok();
+                            }
+                        }
+                    },
+                    PlayerEvent::Finished { reason: FinishReason::Eof, .. } if stops_after_current(stop_after, &songqueue) => {
+                        // the queue moves on, the song after is ready for ctrl+r
+                        songqueue.advance();
+                        self.player.stop().ok();
+                        stop_after = StopAfter::Off;
+                        offset = 0.0;
+                    },
+                    PlayerEvent::Finished { reason: FinishReason::Eof, next } => {
+                        match songqueue.advance() {
+                            // mpv already moved on to it if it was preloaded
+                            Some(s) if next.as_ref() != Some(&s.path) => {
+                                let _ = self.player.play(&s.path[..]);
+                            },
+                            _ => {},
+                        }
+                    },
+                    _ => {},
+                }
+            }
+
+            // store whatever the loudness analysis measured so far
+            let mut analysis_done = false;
+            match &analysis {
+                Some(updates) => {
+                    for update in updates.try_iter() {
+                        match update {
+                            loudness::Update::Progress { done, total } => { analysis_progress = (done, total); },
+                            loudness::Update::Analyzed(s) => {
+                                self.songs.set_replaygain(&s).ok();
+                                songqueue.update_song(&s);
+                            },
+                            loudness::Update::Failed(path, reason) => {
+                                cmdline.message = Some(format!("{}: {}", path, reason));
+                            },
+                            loudness::Update::Done => { analysis_done = true; },
+                        }
+                    }
+                },
+                None => {},
+            }
+            if analysis_done {
+                analysis = None;
+            }
+
+            // keep the next song loaded in the player, the queue might have changed since
+            if songqueue.get_currently_playing().is_some() && self.player.get_song_duration() > 0.0 {
+                let upcoming = if stopping { None } else { songqueue.get_upcoming() };
+                self.player.preload(upcoming.as_ref().map(|s| &s.path[..]));
+            }
+
+            // the gain can depend on what plays around a song, so keep it up to date too
+            for i in [songqueue.get_currently_playing(), songqueue.get_upcoming_index()].iter().flatten() {
+                let song = &songqueue.queue[*i as usize];
+                self.player.apply_replaygain(song, songqueue.is_in_album_run(*i));
+            }
+
+            // the sleep timer pauses once it runs out, fading out over the last minute if asked to
+            match sleep_at {
+                Some(t) if Instant::now() >= t => {
+                    self.player.pause().ok();
+                    self.player.set_volume_scale(1.0).ok();
+                    sleep_at = None;
+                    sleep_minutes = 0;
+                },
+                Some(t) if sleep_fade => {
+                    let left = t.saturating_duration_since(Instant::now()).as_secs_f64();
+                    self.player.set_volume_scale((left / SLEEP_FADE_SECS).min(1.0)).ok();
+                },
+                _ => {},
+            }
+
+            let current_song = songqueue.get_currently_playing_song();
+            let (preset_name, effects) = self.effects_for(current_song.as_ref(), &preset);
+            self.player.set_effects(&effects).ok();
+
+            if self.player.get_song_duration() > 0.0 {
+                offset = self.player.get_time_pos();
+            }
+            // not resuming is a choice too once the song is under way
+            if resume_offer.is_some() && playing_song.is_some() && offset > RESUME_MARGIN_SECS {
+                resume_offer = None;
+            }
+
+            // save the session every so often in case we don't exit cleanly
+            if last_save.elapsed() >= Duration::from_secs(30) {
+                self.save_session(&songqueue, offset, crossfade);
+                // the saved position stays until the offer to resume it is over
+                match &playing_song {
+                    Some(s) if resume_offer.is_none() => self.save_resume(s, offset, &mut navigator),
+                    _ => {},
+                }
+                self.songs.set_state("speed", &speed.to_string()).ok();
+                self.songs.set_state("speed_memory", &speed_memory.to_string()).ok();
+                last_save = Instant::now();
+            }
+
             // draw 
             terminal.draw(|f| {
                 let main_chunk = Layout::default()
@@ -250,26 +1039,54 @@ impl App {
                 let right_top_chunk = right_chunk[0];
                 let right_bottom_chunk = right_chunk[1];
                 let bottom_chunk = main_chunk[1];
-                let block = Block::default()
-                    .title("block")
-                    .borders(Borders::ALL);
-
-                let list = song_list_to_tui_list(&songlist.items, panel == SelectedPanel::SongList);
-                let queue = queue_to_tui_list(&songqueue, panel == SelectedPanel::Queue);
+                let list = song_list_to_tui_list(&songlist.items, panel == SelectedPanel::SongList, &theme);
+                let queue = queue_to_tui_list(&songqueue, panel == SelectedPanel::Queue, &theme);
 
                 f.render_stateful_widget(queue, right_top_chunk, &mut songqueue_state);
-                f.render_widget(block.clone(), right_bottom_chunk);
+                let devices = settings_to_tui_list(&settings, self.player.get_device(), &preset, panel == SelectedPanel::Settings, &theme);
+                f.render_stateful_widget(devices, right_bottom_chunk, &mut settings_state);
                 f.render_stateful_widget(list, center_chunk, &mut songlist_state);
                 // f.render_widget(block.clone(), center_top_chunk);
-                f.render_stateful_widget(nav_to_tui_list(&navigator, panel == SelectedPanel::Nav), left_chunk, &mut navigator_state);
-                let current_song = songqueue.get_currently_playing_song();
-                draw_song_detail(f, bottom_chunk, &self.player, &current_song);
-                draw_search(f, center_top_chunk, &searchbar.query[..], panel == SelectedPanel::Search);
+                f.render_stateful_widget(nav_to_tui_list(&navigator, panel == SelectedPanel::Nav, &theme), left_chunk, &mut navigator_state);
+                let analyzing = analysis.as_ref().map(|_| analysis_progress);
+                let sleep = sleep_at.map(|t| (t.saturating_duration_since(Instant::now()).as_secs_f64(), sleep_fade));
+                areas.gauge = draw_song_detail(f, bottom_chunk, &self.player, &current_song, &songqueue, crossfade, speed_memory, &preset_name, loop_start, stop_after, sleep, resume_offer, analyzing, &theme);
+
+                let lists = [
+                    (left_chunk, navigator_state.selected(), navigator.rows()),
+                    (center_chunk, songlist_state.selected(), songlist.items.len()),
+                    (right_top_chunk, songqueue_state.selected(), songqueue.queue.len()),
+                    (right_bottom_chunk, settings_state.selected(), settings.devices.len() + settings.presets.len()),
+                ];
+                for (i, (rect, selected, len)) in lists.iter().enumerate() {
+                    areas.offsets[i] = list_offset(areas.offsets[i], *selected, *rect, *len);
+                    areas.lens[i] = *len;
+                }
+                areas.nav = left_chunk;
+                areas.songs = center_chunk;
+                areas.queue = right_top_chunk;
+                areas.settings = right_bottom_chunk;
+                areas.search = center_top_chunk;
+                // the command line takes the search bar's place while it's in use
+                if cmdline.is_open() || cmdline.message.is_some() {
+                    draw_search(f, center_top_chunk, "command", &cmdline.display(), cmdline.is_open(), &theme);
+                }
+                else {
+                    draw_search(f, center_top_chunk, "search", &searchbar.query[..], panel == SelectedPanel::Search, &theme);
+                }
             })?;
 
             thread::sleep(Duration::from_millis(20));
         }
 
+        self.save_session(&songqueue, offset, crossfade);
+        match &playing_song {
+            Some(s) if resume_offer.is_none() => self.save_resume(s, offset, &mut navigator),
+            _ => {},
+        }
+        self.songs.set_state("speed", &speed.to_string()).ok();
+        self.songs.set_state("speed_memory", &speed_memory.to_string()).ok();
+
         // restore terminal
         disable_raw_mode()?;
         execute!(
@@ -281,21 +1098,95 @@ impl App {
 
         Ok(())
     }
+
+    fn save_session(&self, queue: &SongQueue, offset: f64, crossfade: f64) {
+        let songs : Vec<Song> = queue.queue.iter().cloned().collect();
+        self.songs.save_queue(&songs, queue.get_order(), queue.get_currently_playing()).ok();
+        self.songs.set_state("offset", &offset.to_string()).ok();
+        self.songs.set_state("shuffle", &queue.get_shuffle().to_string()).ok();
+        self.songs.set_state("repeat", &queue.get_repeat().to_string()).ok();
+        self.songs.set_state("volume", &self.player.get_volume().to_string()).ok();
+        self.songs.set_state("mute", &self.player.is_muted().to_string()).ok();
+        self.songs.set_state("crossfade", &crossfade.to_string()).ok();
+        self.songs.set_state("replaygain", &self.player.get_replaygain().to_string()).ok();
+        self.songs.set_state("preamp", &self.player.get_preamp().to_string()).ok();
+        self.songs.set_state("pitch_correction", &self.player.has_pitch_correction().to_string()).ok();
+    }
+
+    // Remember where a long song was left, or forget it if it's close to the start or the end.
+    // The navigator lists the songs that have a position.
+    fn save_resume(&self, song: &Song, position: f64, navigator: &mut Navigator) {
+        if song.duration < self.config.resume_min_length || song.duration <= 0.0 {
+            return;
+        }
+        let position = if position < RESUME_MARGIN_SECS || position > song.duration - RESUME_MARGIN_SECS { None } else { Some(position) };
+        self.songs.set_resume(&song.title, &song.album, position).ok();
+        navigator.set_category(3, self.songs.get_resume_titles());
+    }
+
+    // the preset bound to the song's album or genre, or the picked one. returns its name too
+    fn effects_for(&self, song: Option<&Song>, picked: &str) -> (String, Effects) {
+        let bound = song.and_then(|s| self.config.album_presets.get(&s.album).or(self.config.genre_presets.get(&s.genre)));
+        let name = bound.map(|b| &b[..]).unwrap_or(picked);
+        return match self.config.presets.iter().find(|p| p.name == name) {
+            Some(p) => (p.name.clone(), p.effects.clone()),
+            None => (String::from("flat"), Effects::flat()),
+        }
+    }
+
+    // a song's own speed comes before its album's
+    fn remembered_speed(&self, song: &Song, memory: SpeedMemory) -> Option<f64> {
+        return match memory {
+            SpeedMemory::Off => None,
+            SpeedMemory::Song => self.songs.get_speed(&song.title, &song.album).or(self.songs.get_speed("", &song.album)),
+            SpeedMemory::Album => self.songs.get_speed("", &song.album),
+        }
+    }
+
+    fn remember_speed(&self, song: &Song, memory: SpeedMemory) {
+        let speed = self.player.get_speed();
+        match memory {
+            SpeedMemory::Off => {},
+            SpeedMemory::Song => { self.songs.set_speed(&song.title, &song.album, speed).ok(); },
+            SpeedMemory::Album => { self.songs.set_speed("", &song.album, speed).ok(); },
+        }
+    }
+}
+
+// whether playback should stop once the current song ends
+fn stops_after_current(stop_after: StopAfter, queue: &SongQueue) -> bool {
+    return match stop_after {
+        StopAfter::Off => false,
+        StopAfter::Track => true,
+        StopAfter::Album => {
+            let current = queue.get_currently_playing_song().map(|s| s.album);
+            current.is_none() || current != queue.get_upcoming().map(|s| s.album)
+        },
+    }
+}
+
+// the first entry a list shows, worked out the way tui does so clicks can be matched to entries
+fn list_offset(offset: usize, selected: Option<usize>, rect: Rect, len: usize) -> usize {
+    let height = rect.height.saturating_sub(2) as usize;
+    if len == 0 || height == 0 {
+        return offset;
+    }
+    let selected = selected.unwrap_or(0).min(len - 1);
+    let offset = offset.min(len - 1);
+    if selected >= offset + height {
+        return selected + 1 - height;
+    }
+    return offset.min(selected);
 }
 
-fn song_list_to_tui_list(song_list : &Vec<Song>, selected: bool) -> List {
+fn song_list_to_tui_list<'a>(song_list : &[Song], selected: bool, theme: &Theme) -> List<'a> {
     // let mut song_list = self.songs.search_all();
     // song_list.sort_by(|a,b| a.album.cmp(&b.album));
     let item_list : Vec<ListItem> = song_list.iter().map(|x| ListItem::new(x.to_string())).collect();
-    let color = if selected { Color::Yellow } else { Color::White };
-    let list = List::new(item_list)
-        .block(Block::default().title("songs").borders(Borders::ALL).border_style(Style::default().fg(color)))
-        .style(Style::default().fg(Color::White))
-        .highlight_symbol(">>");
-    return list;
+    return themed_list(item_list, String::from("songs"), selected, theme);
 }
 
-fn nav_to_tui_list(nav: &navigator::Navigator, selected: bool) -> List {
+fn nav_to_tui_list<'a>(nav: &navigator::Navigator, selected: bool, theme: &Theme) -> List<'a> {
     let mut item_list : Vec<ListItem> = Vec::new();
     for i in &nav.items {
         item_list.push(ListItem::new(i.0.name.clone()));
@@ -305,53 +1196,133 @@ fn nav_to_tui_list(nav: &navigator::Navigator, selected: bool) -> List {
         }
     }
     // item_list.push(ListItem::new("something"));
-    let color = if selected { Color::Yellow } else { Color::White };
-    let list = List::new(item_list)
-        .block(Block::default().title("nav").borders(Borders::ALL).border_style(Style::default().fg(color)))
-        .style(Style::default().fg(Color::White))
-        .highlight_symbol(">>");
-    return list;
+    return themed_list(item_list, String::from("nav"), selected, theme);
 }
 
-fn queue_to_tui_list(q : &songqueue::SongQueue, selected: bool) -> List {
-    let mut item_list : Vec<ListItem> = q.queue.iter().map(|x| ListItem::new(x.to_string())).collect();
+fn queue_to_tui_list<'a>(q : &songqueue::SongQueue, selected: bool, theme: &Theme) -> List<'a> {
+    let shuffle = q.get_shuffle();
+    let mut item_list : Vec<ListItem> = Vec::new();
+    for (i, song) in q.queue.iter().enumerate() {
+        // when shuffled, show where each song is in the play order
+        let item = match shuffle {
+            Shuffle::Off => song.to_string(),
+            _ => format!("{}. {}", q.get_play_position(i as u32).unwrap_or(0) + 1, song.to_string()),
+        };
+        item_list.push(ListItem::new(item));
+    }
+    let title = match shuffle {
+        Shuffle::Off => String::from("song queue"),
+        _ => format!("song queue (shuffle: {})", shuffle.to_string()),
+    };
     match q.get_currently_playing() {
         Some(v) => { 
-            let playing_item = item_list[v as usize].clone().style(Style::default().fg(Color::Green)); 
+            let playing_item = item_list[v as usize].clone().style(Style::default().fg(theme.playing)); 
             item_list[v as usize] = playing_item;
         }
         None => {},
     }
-    let color = if selected { Color::Yellow } else { Color::White };
+    for (i, item) in item_list.iter_mut().enumerate() {
+        if q.is_broken(i as u32) {
+            *item = item.clone().style(Style::default().fg(theme.broken));
+        }
+    }
+    return themed_list(item_list, title, selected, theme);
+}
+
+fn settings_to_tui_list<'a>(settings: &Settings, device: &str, preset: &str, selected: bool, theme: &Theme) -> List<'a> {
+    let mut item_list : Vec<ListItem> = Vec::new();
+    // the ones in use are highlighted like the playing song
+    for d in &settings.devices {
+        let item = ListItem::new(format!("output: {}", d.description));
+        item_list.push(if d.name == device { item.style(Style::default().fg(theme.playing)) } else { item });
+    }
+    for p in &settings.presets {
+        let item = ListItem::new(format!("effects: {}", p));
+        item_list.push(if p == preset { item.style(Style::default().fg(theme.playing)) } else { item });
+    }
+    return themed_list(item_list, String::from("settings"), selected, theme);
+}
+
+// the block, colours and highlight every list shares
+fn themed_list<'a>(item_list: Vec<ListItem<'a>>, title: String, selected: bool, theme: &Theme) -> List<'a> {
+    let color = if selected { theme.focused_border } else { theme.border };
+    // reset leaves the entry's own colour, like the playing song's
+    let mut highlight = Style::default();
+    if theme.selected != Color::Reset {
+        highlight = highlight.fg(theme.selected);
+    }
+    if theme.selected_background != Color::Reset {
+        highlight = highlight.bg(theme.selected_background);
+    }
     let list = List::new(item_list)
-        .block(Block::default().title("song queue").borders(Borders::ALL).border_style(Style::default().fg(color)))
-        .style(Style::default().fg(Color::White))
+        .block(Block::default().title(title).borders(Borders::ALL).border_style(Style::default().fg(color)))
+        .style(Style::default().fg(theme.text).bg(theme.background))
+        .highlight_style(highlight)
         .highlight_symbol(">>");
     return list;
 }
 
-fn song_detail(player : &player::Player) -> LineGauge {
+fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    return format!("{}:{:02}", seconds / 60, seconds % 60);
+}
+
+fn progress_label(player : &player::Player) -> String {
+    let time_left = player.get_time_left();
+    let duration = player.get_song_duration();
+    return format!("{} / {}", format_time(duration - time_left), format_time(duration));
+}
+
+fn song_detail<'a>(player : &player::Player, theme: &Theme) -> LineGauge<'a> {
     let time_left = player.get_time_left();
     let duration = player.get_song_duration();
     let mut fraction_played = (1.0 - time_left / duration).clamp(0.0,1.0);
     if fraction_played.is_nan() {
         fraction_played = 0.0;
     }
+    let paused = player.is_paused();
+    let mut title = String::from(match (player.is_idle(), paused) {
+        (true, _) => "Stopped",
+        (false, true) => "Paused",
+        (false, false) => "Progress",
+    });
+    match player.get_loop() {
+        Some((start, end)) => title.push_str(&format!(" (loop {} - {})", format_time(start), format_time(end))),
+        None => {},
+    }
+    let color = if paused { theme.gauge_paused } else { theme.gauge };
+    let label = progress_label(player);
     let gauge = LineGauge::default()
-        .block(Block::default().title("Progress"))
-        .gauge_style(Style::default().fg(Color::White).bg(Color::Black).add_modifier(Modifier::ITALIC))
+        .block(Block::default().title(title))
+        .gauge_style(Style::default().fg(color).bg(theme.gauge_background).add_modifier(Modifier::ITALIC))
+        .label(label)
         .ratio(fraction_played);
     // let gauge = Paragraph::new(Text::from(fraction_played.to_string()));
     return gauge;
 }
 
+// gives back the line of the progress gauge, for clicks
+#[allow(clippy::too_many_arguments)]
 fn draw_song_detail(
     f : &mut Frame<CrosstermBackend<std::io::Stdout>>, 
     rect : Rect,
     player : &player::Player,
-    song: &Option<Song>) {
+    song: &Option<Song>,
+    queue: &SongQueue,
+    crossfade: f64,
+    speed_memory: SpeedMemory,
+    preset: &str,
+    loop_start: Option<f64>,
+    stop_after: StopAfter,
+    sleep: Option<(f64, bool)>, // seconds left, fading out
+    resume_offer: Option<f64>,
+    analyzing: Option<(usize, usize)>,
+    theme: &Theme) -> Option<Rect> {
     // render container
-    f.render_widget(Block::default().borders(Borders::ALL).title("song details"),rect);
+    let container = Block::default().borders(Borders::ALL).title("song details (ctrl+b: previous)")
+        .border_style(Style::default().fg(theme.border))
+        .style(Style::default().fg(theme.text).bg(theme.background));
+    f.render_widget(container, rect);
 
     // split container
     let chunks = Layout::default()
@@ -386,26 +1357,91 @@ fn draw_song_detail(
     let song_artist_paragraph = Paragraph::new(Text::from(song_artist));
     f.render_widget(song_artist_paragraph, chunks[2]);
 
+    // render playback modes
+    let volume = if player.is_muted() { String::from("muted") } else { format!("{:.0}%", player.get_volume()) };
+    let crossfade = if crossfade > 0.0 { format!("{}s", crossfade) } else { String::from("off") };
+    let replaygain = match player.get_replaygain() {
+        ReplayGain::Off => String::from("off"),
+        r => format!("{} {:+}dB", r.to_string(), player.get_preamp()),
+    };
+    let mut speed = format!("{}x", player.get_speed());
+    if !player.has_pitch_correction() {
+        speed.push_str(" (pitch shifted)");
+    }
+    if speed_memory != SpeedMemory::Off {
+        speed.push_str(&format!(" (per {})", speed_memory.to_string()));
+    }
+    let mut modes = format!("repeat: {}  shuffle: {}  volume: {}  crossfade: {}  replaygain: {}  speed: {}  effects: {}",
+        queue.get_repeat().to_string(), queue.get_shuffle().to_string(), volume, crossfade, replaygain, speed, preset);
+    if stop_after != StopAfter::Off {
+        modes.push_str(&format!("  stop after: {}", stop_after.to_string()));
+    }
+    match sleep {
+        Some((left, fade)) => modes.push_str(&format!("  sleep: {}{}", format_time(left), if fade { " (fade)" } else { "" })),
+        None => {},
+    }
+    match resume_offer {
+        Some(p) => modes.push_str(&format!("  alt+r: resume from {}", format_time(p))),
+        None => {},
+    }
+    match analyzing {
+        Some((done, total)) => modes.push_str(&format!("  analyzing loudness: {}/{}", done, total)),
+        None => {},
+    }
+    f.render_widget(Paragraph::new(Text::from(modes)), chunks[3]);
+
     // render song progress
-    f.render_widget(song_detail(player), chunks[4]);
+    f.render_widget(song_detail(player, theme), chunks[4]);
+
+    // loop markers go on top of the gauge's line, which starts after the label
+    let duration = player.get_song_duration();
+    let gauge_line = Rect {
+        x: chunks[4].x + progress_label(player).len() as u16 + 1,
+        y: chunks[4].y + 1, // below the title
+        width: 0,
+        height: 1,
+    };
+    if duration <= 0.0 || gauge_line.x >= chunks[4].right() || gauge_line.y >= chunks[4].bottom() {
+        return None;
+    }
+    let markers = match player.get_loop() {
+        Some((start, end)) => vec![("A", start), ("B", end)],
+        None => loop_start.map(|s| vec![("A", s)]).unwrap_or_default(),
+    };
+    let length = chunks[4].right() - gauge_line.x;
+    for (marker, seconds) in markers {
+        let offset = ((seconds / duration).clamp(0.0, 1.0) * length as f64) as u16;
+        let rect = Rect {
+            x: (gauge_line.x + offset).min(chunks[4].right() - 1),
+            width: 1,
+            ..gauge_line
+        };
+        f.render_widget(Paragraph::new(Text::from(marker)).style(Style::default().fg(theme.markers)), rect);
+    }
+    // where it can be clicked to seek
+    return Some(Rect { width: length, ..gauge_line });
 }
 
 fn draw_search(
     f : &mut Frame<CrosstermBackend<std::io::Stdout>>, 
     rect : Rect,
+    title: &str,
     query: &str,
-    selected: bool) {
-    let color = if selected { Color::Yellow } else { Color::White };
+    selected: bool,
+    theme: &Theme) {
+    let color = if selected { theme.focused_border } else { theme.border };
     let search_paragraph = Paragraph::new(Text::from(query))
-        .block(Block::default().title("search").borders(Borders::ALL).border_style(Style::default().fg(color)));
+        .style(Style::default().fg(theme.text).bg(theme.background))
+        .block(Block::default().title(title).borders(Borders::ALL).border_style(Style::default().fg(color)));
     f.render_widget(search_paragraph, rect);
 }
 
-pub fn create(songdb: SongDB) -> App {
-    let player = player::new();
+pub fn create(songdb: SongDB, player: player::Player, config: Config, playlist: Option<String>) -> App {
     let app = App {
         songs: songdb,
-        player
+        player,
+        config,
+        playlist,
     };
     return app;
 }
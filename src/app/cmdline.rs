@@ -0,0 +1,136 @@
+use crossterm::event::KeyCode;
+
+use crate::keymap::Action;
+use crate::app::songqueue::{Shuffle, Repeat};
+
+// The line in place of the search bar that takes ":" commands and "/" finds, like in vim
+pub struct CmdLine {
+    pub prompt: Option<char>, // ':' or '/' while it's open
+    pub text: String,
+    pub message: Option<String>, // what went wrong with the last command, shown until the next key
+}
+
+pub enum LineCommand {
+    Action(Action), // anything a key can do, by its keymap name
+    Find(String),
+    Add(bool), // every song in the song list instead of the selected one
+    Shuffle(Option<Shuffle>), // None shuffles what hasn't played yet
+    Repeat(Option<Repeat>), // None cycles
+    SavePlaylist(String),
+    Seek(f64, bool), // seconds, and whether that's from the current position
+    Volume(f64, bool), // same
+}
+
+impl CmdLine {
+    pub fn open(&mut self, prompt: char) {
+        self.prompt = Some(prompt);
+        self.text.clear();
+        self.message = None;
+    }
+
+    pub fn is_open(&self) -> bool {
+        return self.prompt.is_some();
+    }
+
+    // a key typed while it's open, enter gives what was typed
+    pub fn key(&mut self, code: KeyCode) -> Option<Result<LineCommand, String>> {
+        match code {
+            KeyCode::Esc => { self.prompt = None; },
+            KeyCode::Backspace => {
+                // backspace on nothing closes it, like vim
+                match self.text.pop() {
+                    Some(_) => {},
+                    None => { self.prompt = None; },
+                }
+            },
+            KeyCode::Char(c) => self.text.push(c),
+            KeyCode::Enter => {
+                let line = self.text.trim().to_string();
+                let prompt = self.prompt.take();
+                if line.len() == 0 {
+                    return None;
+                }
+                return match prompt {
+                    Some('/') => Some(Ok(LineCommand::Find(line))),
+                    _ => Some(parse(&line)),
+                }
+            },
+            _ => {},
+        }
+        return None;
+    }
+
+    // what's shown in the box
+    pub fn display(&self) -> String {
+        return match (self.prompt, &self.message) {
+            (Some(p), _) => format!("{}{}", p, self.text),
+            (None, Some(m)) => m.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    pub fn new() -> CmdLine {
+        return CmdLine {
+            prompt: None,
+            text: String::new(),
+            message: None,
+        }
+    }
+}
+
+// "add", "add all", "shuffle [off|track|album]", "repeat [consume|one|queue]", "save playlist <name>",
+// "seek [+|-]<time>", "volume [+|-]<level>", or an action from the keymap
+pub fn parse(line: &str) -> Result<LineCommand, String> {
+    let (command, argument) = match line.split_once(' ') {
+        Some((c, a)) => (c, a.trim()),
+        None => (line, ""),
+    };
+    return match (command, argument) {
+        ("add", "") => Ok(LineCommand::Add(false)),
+        ("add", "all") => Ok(LineCommand::Add(true)),
+        ("shuffle", "") => Ok(LineCommand::Shuffle(None)),
+        ("shuffle", s) => s.parse().map(|s| LineCommand::Shuffle(Some(s)))
+            .map_err(|_| String::from("shuffle can be off, track or album")),
+        ("repeat", "") => Ok(LineCommand::Repeat(None)),
+        ("repeat", r) => r.parse().map(|r| LineCommand::Repeat(Some(r)))
+            .map_err(|_| String::from("repeat can be consume, one or queue")),
+        ("save", a) => match a.strip_prefix("playlist ").map(|n| n.trim()) {
+            Some(name) if name.len() > 0 => Ok(LineCommand::SavePlaylist(String::from(name))),
+            _ => Err(String::from("usage: save playlist <name>")),
+        },
+        ("seek", a) => {
+            let (relative, sign, time) = split_sign(a);
+            parse_time(time).map(|t| LineCommand::Seek(sign * t, relative))
+                .ok_or(String::from("usage: seek [+|-]<seconds or m:ss>"))
+        },
+        ("volume", a) => {
+            let (relative, sign, level) = split_sign(a);
+            level.parse::<f64>().ok().filter(|v| v.is_finite()).map(|v| LineCommand::Volume(sign * v, relative))
+                .ok_or(String::from("usage: volume [+|-]<level>"))
+        },
+        ("q", "") => Ok(LineCommand::Action(Action::Quit)),
+        ("pause", "") => Ok(LineCommand::Action(Action::TogglePause)),
+        (a, "") => a.parse().map(LineCommand::Action)
+            .map_err(|_| format!("unknown command \"{}\"", a)),
+        (c, _) => Err(format!("\"{}\" doesn't take anything after it", c)),
+    }
+}
+
+// "+10" is (relative, 1, "10"), "-10" (relative, -1, "10"), "10" (absolute, 1, "10")
+fn split_sign(s: &str) -> (bool, f64, &str) {
+    return match (s.strip_prefix('+'), s.strip_prefix('-')) {
+        (Some(rest), _) => (true, 1.0, rest),
+        (_, Some(rest)) => (true, -1.0, rest),
+        _ => (false, 1.0, s),
+    }
+}
+
+// "90", "1:30" or "1:02:03" in seconds
+fn parse_time(s: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for part in s.split(':') {
+        let value : f64 = part.parse().ok().filter(|v : &f64| v.is_finite() && *v >= 0.0)?;
+        seconds = seconds * 60.0 + value;
+    }
+    return Some(seconds);
+}
@@ -9,7 +9,9 @@ pub enum Event {
     Right,
     Accept,
     Back,
-    Nothing,
+    Select(usize), // go to an entry, past the end goes to the last one
+    Move(isize), // move by this many entries, stopping at the ends
+    Find(String, bool), // go to the next entry containing the text, or the previous one if false
 }
 
 pub enum Response {
@@ -18,8 +20,24 @@ pub enum Response {
     Query(Query),
     QueryAny(String),
     StopSong,
+    SetDevice(String), // name of the output device
+    SetPreset(String), // name of the effects preset
 }
 
 pub trait Command {
     fn command(&mut self, event : &Event) -> Option<Response>;
 }
+
+// where the next entry after from containing pattern is, going around at the ends. case doesn't matter
+pub fn find(items: &[String], from: Option<usize>, pattern: &str, forward: bool) -> Option<usize> {
+    let pattern = pattern.to_lowercase();
+    let len = items.len();
+    let start = from.unwrap_or(if forward { len.saturating_sub(1) } else { 0 });
+    for step in 1..=len {
+        let i = if forward { (start + step) % len } else { (start + len * step - step) % len };
+        if items[i].to_lowercase().contains(&pattern) {
+            return Some(i);
+        }
+    }
+    return None;
+}
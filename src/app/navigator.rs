@@ -1,5 +1,5 @@
 use std::cmp;
-use crate::app::command::{Event,Command,Response};
+use crate::app::command::{self,Event,Command,Response};
 use crate::songdb::Query;
 
 pub struct Category {
@@ -23,7 +23,31 @@ impl Command for Navigator {
             Event::Down => {self.next(); None },
             Event::Right => {self.next_category(); None },
             Event::Left => {self.back_category(); None },
-            Event::Accept => Some(Response::Query(self.query())),
+            Event::Accept => self.query().map(Response::Query),
+            // both within the category
+            Event::Select(i) => {
+                let cat_size = self.items[self.selection.0 as usize].2.len();
+                if cat_size > 0 {
+                    self.selection.1 = Some((*i).min(cat_size - 1) as u32);
+                }
+                None
+            },
+            Event::Move(n) => {
+                let cat_size = self.items[self.selection.0 as usize].2.len();
+                if cat_size > 0 {
+                    let from = self.selection.1.unwrap_or(0) as isize;
+                    self.selection.1 = Some((from + n).clamp(0, cat_size as isize - 1) as u32);
+                }
+                None
+            },
+            Event::Find(pattern, forward) => {
+                let entries = &self.items[self.selection.0 as usize].2;
+                match command::find(entries, self.selection.1.map(|v| v as usize), pattern, *forward) {
+                    Some(i) => { self.selection.1 = Some(i as u32); },
+                    None => {},
+                }
+                None
+            },
             _ => None,
         };
     }
@@ -32,6 +56,9 @@ impl Command for Navigator {
 impl Navigator {
     pub fn back(&mut self) {
         let cat_size = self.items.get(self.selection.0 as usize).unwrap().2.len();
+        if cat_size == 0 {
+            return;
+        }
         self.selection.1 = match self.selection.1 {
             Some(v) => Some(cmp::min(v.wrapping_sub(1), cat_size as u32 - 1)),
             None => Some(0),
@@ -40,6 +67,9 @@ impl Navigator {
 
     pub fn next(&mut self) {
         let cat_size = self.items.get(self.selection.0 as usize).unwrap().2.len();
+        if cat_size == 0 {
+            return;
+        }
         self.selection.1 = match self.selection.1 {
             Some(v) => Some((v + 1).clamp(0,cat_size as u32 - 1)),
             None => Some(0),
@@ -56,13 +86,51 @@ impl Navigator {
         self.selection.1 = Some(0);
     }
 
+    // a row as it's shown, with the category names in between their entries
+    pub fn select_row(&mut self, row: usize) {
+        let mut first = 0;
+        for (i, category) in self.items.iter().enumerate() {
+            let len = category.2.len();
+            // the name picks the category, like left/right do
+            if row == first {
+                self.selection = (i as u32, Some(0));
+                return;
+            }
+            if row <= first + len {
+                self.selection = (i as u32, Some((row - first - 1) as u32));
+                return;
+            }
+            first += len + 1;
+        }
+    }
+
+    // how many rows it takes to show
+    pub fn rows(&self) -> usize {
+        return self.items.iter().map(|c| c.2.len() + 1).sum();
+    }
+
     pub fn fill_category(&mut self, idx : usize, content : &mut Vec<String>) {
         if idx < self.items.len() {
             self.items[idx].2.append(content);
         }
     }
 
-    fn query(&self) -> Query {
+    // replace what's in a category, for ones that change while running
+    pub fn set_category(&mut self, idx : usize, content : Vec<String>) {
+        if idx < self.items.len() {
+            self.items[idx].2 = content;
+            if self.selection.0 as usize == idx {
+                let len = self.items[idx].2.len() as u32;
+                self.selection.1 = match self.selection.1 {
+                    Some(_) if len == 0 => None,
+                    Some(v) => Some(cmp::min(v, len - 1)),
+                    None => None,
+                };
+            }
+        }
+    }
+
+    fn query(&self) -> Option<Query> {
         let table = self.items[self.selection.0 as usize].0.table.clone();
         let mut query = Query {
             title:     None,
@@ -76,20 +144,22 @@ impl Navigator {
             lyrics:    None,
             hash:      None,
         };
-        let query_content = self.items[self.selection.0 as usize].2[self.selection.1.unwrap() as usize].clone();
+        let query_content = self.items[self.selection.0 as usize].2.get(self.selection.1? as usize)?.clone();
         match &table[..] {
             "Album" => { query.album = Some(query_content); },
             "Artist" => { query.artist= Some(query_content); },
             "Genre" => { query.genre = Some(query_content); },
+            "Resume" => { query.title = Some(query_content); },
             _ => {},
         };
-        return query;
+        return Some(query);
     }
 
     pub fn get_selection(&self) -> (u32,Option<u32>) {
         return self.selection;
     }
 
+    #[allow(clippy::vec_init_then_push)]
     pub fn new() -> Navigator {
         let mut items: Vec<(Category,bool,Vec<String>)> = Vec::new();
         items.push((
@@ -116,6 +186,15 @@ impl Navigator {
                 true,
                 Vec::new()
                 ));
+        // songs with a saved position, by title
+        items.push((
+                Category {
+                    name: String::from("Partially listened"),
+                    table: String::from("Resume"),
+                },
+                true,
+                Vec::new()
+                ));
         let selection: (u32,Option<u32>) = (0,None);
         let nav = Navigator {
             items,
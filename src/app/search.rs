@@ -1,11 +1,11 @@
 use crate::app::command::{Event,Command,Response};
-use std::cmp;
 
 pub struct Search {
     pub query : String
 }
 
 impl Command for Search {
+    #[allow(clippy::manual_unwrap_or, clippy::manual_unwrap_or_default, clippy::unnecessary_cast)]
     fn command(&mut self, event: &Event) -> Option<Response> {
         match event {
             Event::Char(c) => {
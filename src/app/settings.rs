@@ -0,0 +1,73 @@
+use crate::app::command::{Event,Command,Response};
+use crate::player::backend::AudioDevice;
+
+// The settings view. Lists the output devices followed by the effect presets, picking one
+// switches to it.
+pub struct Settings {
+    pub devices: Vec<AudioDevice>,
+    pub presets: Vec<String>, // preset names
+    selection: usize,
+}
+
+impl Command for Settings {
+    fn command(&mut self, event: &Event) -> Option<Response> {
+        match event {
+            Event::Up if self.selection > 0 => {
+                self.selection -= 1;
+            },
+            Event::Down if self.selection + 1 < self.len() => {
+                self.selection += 1;
+            },
+            Event::Select(i) if self.len() > 0 => {
+                self.selection = (*i).min(self.len() - 1);
+            },
+            Event::Move(n) if self.len() > 0 => {
+                self.selection = (self.selection as isize + n).clamp(0, self.len() as isize - 1) as usize;
+            },
+            Event::Accept => {
+                match self.devices.get(self.selection) {
+                    Some(d) => { return Some(Response::SetDevice(d.name.clone())); },
+                    None => {},
+                }
+                match self.presets.get(self.selection - self.devices.len()) {
+                    Some(p) => { return Some(Response::SetPreset(p.clone())); },
+                    None => {},
+                }
+            },
+            _ => {},
+        }
+        return None;
+    }
+}
+
+impl Settings {
+    fn len(&self) -> usize {
+        return self.devices.len() + self.presets.len();
+    }
+
+    pub fn get_selection(&self) -> Option<usize> {
+        if self.len() == 0 {
+            return None;
+        }
+        return Some(self.selection);
+    }
+
+    // devices come and go, keep the same entry selected
+    pub fn set_devices(&mut self, devices: Vec<AudioDevice>) {
+        if self.devices.len() > 0 && self.selection >= self.devices.len() {
+            self.selection = self.selection - self.devices.len() + devices.len();
+        }
+        self.devices = devices;
+        if self.selection >= self.len() {
+            self.selection = self.len().saturating_sub(1);
+        }
+    }
+
+    pub fn new(presets: Vec<String>) -> Settings {
+        return Settings {
+            devices: Vec::new(),
+            presets,
+            selection: 0,
+        }
+    }
+}
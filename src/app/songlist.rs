@@ -1,9 +1,10 @@
 use std::cmp::Ordering;
 
-use crate::app::command::{Event,Command,Response};
-use crate::song;
+use crate::app::command::{self,Event,Command,Response};
 use crate::song::Song;
 
+// only album order is used so far
+#[allow(dead_code)]
 pub enum SongOrder {
     TrackNum,
     Album,
@@ -43,6 +44,19 @@ impl Command for SongList {
                     self.selection = self.items.len() as i32 - 1;
                 }
             },
+            Event::Select(i) if self.items.len() > 0 => {
+                self.selection = (*i).min(self.items.len() - 1) as i32;
+            },
+            Event::Move(n) if self.items.len() > 0 => {
+                self.selection = (self.selection as isize + n).clamp(0, self.items.len() as isize - 1) as i32;
+            },
+            Event::Find(pattern, forward) => {
+                let items : Vec<String> = self.items.iter().map(|s| s.to_string()).collect();
+                match command::find(&items, Some(self.selection as usize), pattern, *forward) {
+                    Some(i) => { self.selection = i as i32; },
+                    None => {},
+                }
+            },
             Event::Accept => {
                 let selected_song = self.get_selected_song();
                 match selected_song {
@@ -57,6 +71,7 @@ impl Command for SongList {
 }
 
 impl SongList {
+    #[allow(dead_code)]
     pub fn replace_items(&mut self, songs: Vec<Song>) {
         self.items = songs;
         self.selection = 0;
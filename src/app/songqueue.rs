@@ -1,15 +1,89 @@
-use std::collections::VecDeque;
+use std::collections::{VecDeque, HashSet};
+use std::str::FromStr;
+use rand::seq::SliceRandom;
 
-use crate::app::command::{Event,Command,Response};
+use crate::app::command::{self,Event,Command,Response};
 use crate::song::Song;
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum Shuffle {
+    Off,
+    Track,
+    Album, // albums in random order, tracks in order
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for Shuffle {
+    fn to_string(&self) -> String {
+        return match self {
+            Shuffle::Off   => String::from("off"),
+            Shuffle::Track => String::from("track"),
+            Shuffle::Album => String::from("album"),
+        }
+    }
+}
+
+impl FromStr for Shuffle {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Shuffle, ()> {
+        return match s {
+            "off"   => Ok(Shuffle::Off),
+            "track" => Ok(Shuffle::Track),
+            "album" => Ok(Shuffle::Album),
+            _ => Err(()),
+        }
+    }
+}
+
+// what happens to a song once it finishes
+#[derive(Clone, Copy, PartialEq)]
+pub enum Repeat {
+    Consume, // remove it from the queue
+    One,     // play it again
+    Queue,   // keep it and go back to the start after the last song
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for Repeat {
+    fn to_string(&self) -> String {
+        return match self {
+            Repeat::Consume => String::from("consume"),
+            Repeat::One     => String::from("one"),
+            Repeat::Queue   => String::from("queue"),
+        }
+    }
+}
+
+impl FromStr for Repeat {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Repeat, ()> {
+        return match s {
+            "consume" => Ok(Repeat::Consume),
+            "one"     => Ok(Repeat::One),
+            "queue"   => Ok(Repeat::Queue),
+            _ => Err(()),
+        }
+    }
+}
+
+// how many played songs are remembered
+const HISTORY_LEN: usize = 100;
+
 pub struct SongQueue {
     pub queue: VecDeque<Song>,
     selection: Option<u32>,
     currently_playing: Option<u32>,
+    // indices into queue in the order they are played. The queue itself keeps the order it's
+    // shown in so turning shuffle off brings that order back
+    order: Vec<u32>,
+    shuffle: Shuffle,
+    repeat: Repeat,
+    history: Vec<Song>, // most recently played last
+    broken: HashSet<String>, // paths of songs that couldn't be played, they're skipped
 }
 
 impl Command for SongQueue {
+    #[allow(clippy::partialeq_to_none)]
     fn command(&mut self, event: &Event) -> Option<Response> {
         return match event {
             Event::Up =>    { self.select_up(); None },
@@ -26,9 +100,31 @@ impl Command for SongQueue {
             },
             Event::Left =>  { self.swap_up(); None }
             Event::Right =>  { self.swap_down(); None }
+            Event::Select(i) => {
+                if self.queue.len() > 0 {
+                    self.selection = Some((*i).min(self.queue.len() - 1) as u32);
+                }
+                None
+            },
+            Event::Move(n) => {
+                if self.queue.len() > 0 {
+                    let from = self.selection.unwrap_or(0) as isize;
+                    self.selection = Some((from + n).clamp(0, self.queue.len() as isize - 1) as u32);
+                }
+                None
+            },
+            Event::Find(pattern, forward) => {
+                let items : Vec<String> = self.queue.iter().map(|s| s.to_string()).collect();
+                match command::find(&items, self.selection.map(|v| v as usize), pattern, *forward) {
+                    Some(i) => { self.selection = Some(i as u32); },
+                    None => {},
+                }
+                None
+            },
             Event::Accept => { 
                 match self.selection {
                     Some(v) => {
+                        self.push_history();
                         self.currently_playing = Some(v);
                         Some(Response::PlaySong(self.queue[v as usize].clone()))
                     },
@@ -41,6 +137,7 @@ impl Command for SongQueue {
 }
 
 impl SongQueue {
+    #[allow(dead_code, clippy::question_mark, clippy::manual_map)]
     pub fn get_selected_song(&self) -> Option<Song> {
         let i = match self.selection {
             Some(v) => v,
@@ -52,6 +149,7 @@ impl SongQueue {
         }
     }
 
+    #[allow(clippy::question_mark, clippy::manual_map)]
     pub fn get_currently_playing_song(&self) -> Option<Song> {
         let i = match self.currently_playing {
             Some(v) => v,
@@ -108,6 +206,7 @@ impl SongQueue {
         return v;
     }
 
+    #[allow(clippy::manual_map)]
     pub fn swap_up(&mut self) {
         self.selection = match self.selection {
             Some(x) => {
@@ -120,12 +219,14 @@ impl SongQueue {
                     None => None,
                 };
                 self.queue.swap(x as usize, new as usize);
+                self.swap_order(x, new);
                 Some(new)
             },
             None => None,
         }
     }
 
+    #[allow(clippy::manual_map)]
     pub fn swap_down(&mut self) {
         self.selection = match self.selection {
             Some(x) => {
@@ -138,27 +239,63 @@ impl SongQueue {
                     None => None,
                 };
                 self.queue.swap(x as usize, new as usize);
+                self.swap_order(x, new);
                 Some(new)
             },
             None => None,
         }
     }
 
+    // drag the selected song to another place, one swap at a time so the play order follows
+    pub fn move_selected(&mut self, to: usize) {
+        let to = to.min(self.queue.len().saturating_sub(1)) as u32;
+        loop {
+            match self.selection {
+                Some(x) if x > to => self.swap_up(),
+                Some(x) if x < to => self.swap_down(),
+                _ => { return; },
+            }
+        }
+    }
+
+    // unshuffled, swapping changes what plays next. shuffled, each song keeps its place in the
+    // play order
+    fn swap_order(&mut self, a: u32, b: u32) {
+        if self.shuffle == Shuffle::Off {
+            return;
+        }
+        for v in self.order.iter_mut() {
+            *v = SongQueue::swap_if_other(a,b,*v);
+        }
+    }
+
     pub fn push(&mut self, song: Song) {
         self.queue.push_back(song);
+        self.order.push(self.queue.len() as u32 - 1);
+    }
+
+    // replace the queued copies of a song after it changed in the database
+    pub fn update_song(&mut self, song: &Song) {
+        for s in self.queue.iter_mut() {
+            if s.path == song.path {
+                *s = song.clone();
+            }
+        }
     }
 
+    #[allow(clippy::unnecessary_cast)]
     pub fn remove(&mut self, idx: usize) {
         if (idx as u32) < self.queue.len() as u32 {
             self.queue.remove(idx as usize);
+            self.order.retain(|v| *v != idx as u32);
+            for v in self.order.iter_mut() {
+                if *v > idx as u32 { *v -= 1; }
+            }
+            // songs after the removed one move up, and nothing plays if it was the one playing
             self.currently_playing = match self.currently_playing {
-                Some(v) => {
-                    let mut result = Some(v);
-                    if v == idx as u32 {
-                        result = None;
-                    }
-                    result
-                },
+                Some(v) if v == idx as u32 => None,
+                Some(v) if v > idx as u32 => Some(v - 1),
+                Some(v) => Some(v),
                 None => None,
             };
             self.selection = match self.selection {
@@ -197,19 +334,387 @@ impl SongQueue {
     pub fn pop_currently_playing(&mut self) {
         match self.currently_playing {
             Some(x) => {
+                let next = self.get_next();
                 self.remove(x as usize);
-                self.set_currently_playing(x);
+                match next {
+                    // indices after the removed song shift down by one
+                    Some(v) => self.set_currently_playing(if v > x { v - 1 } else { v }),
+                    None => {},
+                }
+            },
+            None => {},
+        }
+    }
+
+    // move on from the song that just finished according to the repeat mode. returns the song
+    // to play next, if there is one
+    pub fn advance(&mut self) -> Option<Song> {
+        if self.repeat != Repeat::One {
+            self.push_history();
+        }
+        match self.repeat {
+            Repeat::Consume => self.pop_currently_playing(),
+            Repeat::One => {},
+            Repeat::Queue => {
+                self.currently_playing = match self.get_next() {
+                    Some(v) => Some(v),
+                    None => self.get_first(),
+                };
+            },
+        }
+        return self.get_currently_playing_song();
+    }
+
+    fn push_history(&mut self) {
+        match self.get_currently_playing_song() {
+            Some(s) => {
+                if self.history.len() >= HISTORY_LEN {
+                    self.history.remove(0);
+                }
+                self.history.push(s);
             },
             None => {},
         }
     }
 
+    // step back to the last played song. it's put back in front of the current song if it's not
+    // in the queue anymore. returns the song to play, if there is one
+    pub fn previous(&mut self) -> Option<Song> {
+        let song = self.history.pop()?;
+        let idx = match self.queue.iter().position(|s| s.path == song.path) {
+            Some(v) => v as u32,
+            None => {
+                let idx = self.currently_playing.unwrap_or(0);
+                self.insert(idx as usize, song);
+                idx
+            },
+        };
+        self.currently_playing = Some(idx);
+        return self.get_currently_playing_song();
+    }
+
+    // put a song at idx, playing right before the current song
+    fn insert(&mut self, idx: usize, song: Song) {
+        let play_position = self.currently_playing.and_then(|v| self.get_play_position(v)).unwrap_or(0);
+        self.queue.insert(idx, song);
+        for v in self.order.iter_mut() {
+            if *v >= idx as u32 { *v += 1; }
+        }
+        if self.shuffle == Shuffle::Off {
+            self.order = (0..self.queue.len() as u32).collect();
+        }
+        else {
+            self.order.insert(play_position, idx as u32);
+        }
+        self.currently_playing = self.currently_playing.map(|v| if v >= idx as u32 { v + 1 } else { v });
+        self.selection = self.selection.map(|v| if v >= idx as u32 { v + 1 } else { v });
+    }
+
+    // the song advance() would move on to
+    pub fn get_upcoming(&self) -> Option<Song> {
+        return self.queue.get(self.get_upcoming_index()? as usize).cloned();
+    }
+
+    pub fn get_upcoming_index(&self) -> Option<u32> {
+        return match self.repeat {
+            Repeat::Consume => self.get_next(),
+            Repeat::One => self.currently_playing,
+            Repeat::Queue => self.get_next().or(self.get_first()),
+        }
+    }
+
+    // whether the song plays right before or after a song of the same album
+    pub fn is_in_album_run(&self, idx: u32) -> bool {
+        let album = match self.queue.get(idx as usize) {
+            Some(s) => &s.album,
+            None => { return false; },
+        };
+        let position = match self.get_play_position(idx) {
+            Some(v) => v,
+            None => { return false; },
+        };
+        let before = position.checked_sub(1).and_then(|p| self.order.get(p));
+        let after = self.order.get(position + 1);
+        return [before, after].iter().flatten().any(|v| &self.queue[**v as usize].album == album);
+    }
+
+    pub fn get_repeat(&self) -> Repeat {
+        return self.repeat;
+    }
+
+    pub fn set_repeat(&mut self, repeat: Repeat) {
+        self.repeat = repeat;
+    }
+
+    pub fn cycle_repeat(&mut self) {
+        self.repeat = match self.repeat {
+            Repeat::Consume => Repeat::One,
+            Repeat::One     => Repeat::Queue,
+            Repeat::Queue   => Repeat::Consume,
+        };
+    }
+
+    // the song that plays when nothing is playing yet
+    pub fn get_first(&self) -> Option<u32> {
+        return self.order.iter().copied().find(|v| !self.is_broken(*v));
+    }
+
+    // the song that plays after the current one
+    pub fn get_next(&self) -> Option<u32> {
+        let i = self.get_play_position(self.currently_playing?)?;
+        return self.order[i + 1..].iter().copied().find(|v| !self.is_broken(*v));
+    }
+
+    pub fn mark_broken(&mut self, path: &str) {
+        self.broken.insert(String::from(path));
+    }
+
+    pub fn is_broken(&self, idx: u32) -> bool {
+        return match self.queue.get(idx as usize) {
+            Some(s) => self.broken.contains(&s.path),
+            None => false,
+        }
+    }
+
+    // Move on from a song that couldn't be played. Unlike advance() it stays in the queue
+    // whatever the repeat mode. returns the song to play next, if there is one
+    pub fn skip(&mut self) -> Option<Song> {
+        let next = match self.repeat {
+            Repeat::Queue => self.get_next().or(self.get_first()),
+            _ => self.get_next(),
+        };
+        self.currently_playing = next;
+        return self.get_currently_playing_song();
+    }
+
+    // where a song of the queue is in the play order
+    pub fn get_play_position(&self, idx: u32) -> Option<usize> {
+        return self.order.iter().position(|v| *v == idx);
+    }
+
+    pub fn get_shuffle(&self) -> Shuffle {
+        return self.shuffle;
+    }
+
+    pub fn cycle_shuffle(&mut self) {
+        let shuffle = match self.shuffle {
+            Shuffle::Off   => Shuffle::Track,
+            Shuffle::Track => Shuffle::Album,
+            Shuffle::Album => Shuffle::Off,
+        };
+        self.set_shuffle(shuffle);
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: Shuffle) {
+        self.shuffle = shuffle;
+        let mut rng = rand::thread_rng();
+        let mut order : Vec<u32> = (0..self.queue.len() as u32).collect();
+        match shuffle {
+            Shuffle::Off => {},
+            Shuffle::Track => {
+                order.shuffle(&mut rng);
+            },
+            Shuffle::Album => {
+                // group by album in the order albums first show up, keeping track order. albums
+                // of different artists can have the same name
+                let mut albums : Vec<Vec<u32>> = Vec::new();
+                for i in order {
+                    let song = &self.queue[i as usize];
+                    match albums.iter_mut().find(|a| same_album(&self.queue[a[0] as usize], song)) {
+                        Some(a) => a.push(i),
+                        None => albums.push(vec![i]),
+                    }
+                }
+                for a in albums.iter_mut() {
+                    a.sort_by_key(|v| self.queue[*v as usize].track_num);
+                }
+                albums.shuffle(&mut rng);
+                order = albums.concat();
+            },
+        }
+
+        // the current song stays current, so it goes first to not replay the rest
+        match self.currently_playing {
+            Some(v) if shuffle == Shuffle::Track => {
+                order.retain(|x| *x != v);
+                order.insert(0, v);
+            },
+            Some(v) if shuffle == Shuffle::Album => {
+                let song = &self.queue[v as usize];
+                let (mut current, rest) : (Vec<u32>, Vec<u32>) = order.iter()
+                    .partition(|x| same_album(&self.queue[**x as usize], song));
+                current.extend(rest);
+                order = current;
+            },
+            _ => {},
+        }
+        self.order = order;
+    }
+
+    pub fn get_order(&self) -> &Vec<u32> {
+        return &self.order;
+    }
+
+    // put back a queue saved with SongDB::save_queue. entries are (song, place in play order,
+    // is playing) in the order they are shown
+    pub fn restore(&mut self, entries: Vec<(Song,i64,bool)>, shuffle: Shuffle) {
+        let mut order : Vec<u32> = (0..entries.len() as u32).collect();
+        order.sort_by_key(|v| entries[*v as usize].1);
+        self.currently_playing = entries.iter().position(|e| e.2).map(|v| v as u32);
+        self.queue = entries.into_iter().map(|e| e.0).collect();
+        self.selection = None;
+        self.order = order;
+        self.shuffle = shuffle;
+    }
+
+    // reshuffle only what hasn't been played yet
+    pub fn shuffle_remaining(&mut self) {
+        let start = match self.currently_playing.and_then(|v| self.get_play_position(v)) {
+            Some(i) => i + 1,
+            None => 0,
+        };
+        if self.shuffle == Shuffle::Off {
+            self.shuffle = Shuffle::Track;
+        }
+        self.order[start..].shuffle(&mut rand::thread_rng());
+    }
+
     pub fn new() -> SongQueue {
         let q = SongQueue {
             queue: VecDeque::new(),
             selection: None,
             currently_playing: None,
+            order: Vec::new(),
+            shuffle: Shuffle::Off,
+            repeat: Repeat::Consume,
+            history: Vec::new(),
+            broken: HashSet::new(),
+        };
+        return q;
+    }
+}
+
+fn same_album(a: &Song, b: &Song) -> bool {
+    return a.album == b.album && a.album_artist == b.album_artist;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str) -> Song {
+        return Song {
+            title: String::from(title),
+            album: String::from("album"),
+            artist: String::from("artist"),
+            album_artist: String::from("artist"),
+            genre: String::new(),
+            year: 0,
+            track_num: 0,
+            duration: 180.0,
+            path: String::from(title),
+            lyrics: String::new(),
+            hash: String::new(),
+            size: 0,
+            track_gain: None,
+            track_peak: None,
+            album_gain: None,
+            album_peak: None,
         };
+    }
+
+    // a, b and c with a playing
+    fn queue(repeat: Repeat) -> SongQueue {
+        let mut q = SongQueue::new();
+        for title in ["a", "b", "c"] {
+            q.push(song(title));
+        }
+        q.set_repeat(repeat);
+        q.set_currently_playing(0);
         return q;
     }
+
+    fn titles(q: &SongQueue) -> Vec<&str> {
+        return q.queue.iter().map(|s| &s.title[..]).collect();
+    }
+
+    #[test]
+    fn consume_removes_what_played() {
+        let mut q = queue(Repeat::Consume);
+        assert_eq!(q.advance().map(|s| s.title), Some(String::from("b")));
+        assert_eq!(titles(&q), vec!["b", "c"]);
+        q.advance();
+        assert_eq!(q.advance().map(|s| s.title), None);
+        assert_eq!(titles(&q).len(), 0);
+    }
+
+    #[test]
+    fn repeat_one_stays() {
+        let mut q = queue(Repeat::One);
+        assert_eq!(q.advance().map(|s| s.title), Some(String::from("a")));
+        assert_eq!(titles(&q), vec!["a", "b", "c"]);
+        // nothing new played, so there's nothing to go back to
+        assert_eq!(q.previous().map(|s| s.title), None);
+    }
+
+    #[test]
+    fn repeat_queue_wraps_around() {
+        let mut q = queue(Repeat::Queue);
+        q.advance();
+        q.advance();
+        assert_eq!(q.advance().map(|s| s.title), Some(String::from("a")));
+        assert_eq!(titles(&q), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn broken_songs_are_skipped() {
+        let mut q = queue(Repeat::Queue);
+        q.mark_broken("b");
+        assert_eq!(q.advance().map(|s| s.title), Some(String::from("c")));
+        assert_eq!(q.advance().map(|s| s.title), Some(String::from("a")));
+    }
+
+    #[test]
+    fn previous_goes_back_through_history() {
+        let mut q = queue(Repeat::Queue);
+        q.advance();
+        q.advance();
+        assert_eq!(q.previous().map(|s| s.title), Some(String::from("b")));
+        assert_eq!(q.previous().map(|s| s.title), Some(String::from("a")));
+        assert_eq!(q.previous().map(|s| s.title), None);
+    }
+
+    #[test]
+    fn removing_keeps_the_playing_song() {
+        let mut q = queue(Repeat::Consume);
+        q.set_currently_playing(2);
+        q.remove(0);
+        assert_eq!(q.get_currently_playing_song().map(|s| s.title), Some(String::from("c")));
+        q.remove(1);
+        assert_eq!(q.get_currently_playing(), None);
+    }
+
+    #[test]
+    fn previous_puts_consumed_songs_back() {
+        let mut q = queue(Repeat::Consume);
+        q.advance();
+        assert_eq!(q.previous().map(|s| s.title), Some(String::from("a")));
+        assert_eq!(titles(&q), vec!["a", "b", "c"]);
+        assert_eq!(q.get_next(), Some(1));
+    }
+
+    #[test]
+    fn album_shuffle_keeps_artists_apart() {
+        let mut q = SongQueue::new();
+        for (title, artist, track) in [("a1", "a", 1), ("b1", "b", 1), ("a2", "a", 2), ("b2", "b", 2)] {
+            let mut s = song(title);
+            s.album = String::from("greatest hits");
+            s.album_artist = String::from(artist);
+            s.track_num = track;
+            q.push(s);
+        }
+        q.set_shuffle(Shuffle::Album);
+        let order : Vec<&str> = q.get_order().iter().map(|i| &q.queue[*i as usize].title[..]).collect();
+        assert!(order == vec!["a1", "a2", "b1", "b2"] || order == vec!["b1", "b2", "a1", "a2"]);
+    }
 }
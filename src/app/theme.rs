@@ -0,0 +1,122 @@
+use tui::style::Color;
+
+// The colours everything is drawn with
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    pub text: Color,
+    pub background: Color,
+    pub border: Color,
+    pub focused_border: Color, // of the panel keys go to
+    pub selected: Color, // the entry under ">>"
+    pub selected_background: Color,
+    pub playing: Color, // the song that's playing, and the device and preset in use
+    pub broken: Color, // songs that can't be played
+    pub gauge: Color,
+    pub gauge_background: Color,
+    pub gauge_paused: Color,
+    pub markers: Color, // of a/b loops on the gauge
+}
+
+// what's there without any theme files, "dark" is the default
+pub fn builtin_themes() -> Vec<Theme> {
+    return vec![
+        Theme {
+            name: String::from("dark"),
+            text: Color::White,
+            background: Color::Reset,
+            border: Color::White,
+            focused_border: Color::Yellow,
+            selected: Color::Reset,
+            selected_background: Color::Reset,
+            playing: Color::Green,
+            broken: Color::Red,
+            gauge: Color::White,
+            gauge_background: Color::Black,
+            gauge_paused: Color::DarkGray,
+            markers: Color::Yellow,
+        },
+        Theme {
+            name: String::from("light"),
+            text: Color::Black,
+            background: Color::Reset,
+            border: Color::DarkGray,
+            focused_border: Color::Blue,
+            selected: Color::Reset,
+            selected_background: Color::Reset,
+            playing: Color::Green,
+            broken: Color::Red,
+            gauge: Color::Blue,
+            gauge_background: Color::Gray,
+            gauge_paused: Color::DarkGray,
+            markers: Color::Magenta,
+        },
+        Theme {
+            name: String::from("high-contrast"),
+            text: Color::White,
+            background: Color::Black,
+            border: Color::White,
+            focused_border: Color::LightYellow,
+            selected: Color::Black,
+            selected_background: Color::White,
+            playing: Color::LightGreen,
+            broken: Color::LightRed,
+            gauge: Color::LightYellow,
+            gauge_background: Color::Black,
+            gauge_paused: Color::Gray,
+            markers: Color::LightCyan,
+        },
+    ];
+}
+
+// a name ("light_blue"), a number from the 256 colour palette ("208") or "#rrggbb"
+pub fn parse_color(s: &str) -> Option<Color> {
+    match s.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 => {
+            let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+            return Some(fit_to_terminal(Color::Rgb(channel(0)?, channel(2)?, channel(4)?)));
+        },
+        Some(_) => { return None; },
+        None => {},
+    }
+    match s.parse::<u8>() {
+        Ok(i) => { return Some(Color::Indexed(i)); },
+        Err(_) => {},
+    }
+    return match &s.to_lowercase().replace(['-', ' '], "_")[..] {
+        "reset" | "default" => Some(Color::Reset),
+        "black"         => Some(Color::Black),
+        "red"           => Some(Color::Red),
+        "green"         => Some(Color::Green),
+        "yellow"        => Some(Color::Yellow),
+        "blue"          => Some(Color::Blue),
+        "magenta"       => Some(Color::Magenta),
+        "cyan"          => Some(Color::Cyan),
+        "gray" | "grey" => Some(Color::Gray),
+        "dark_gray" | "dark_grey" => Some(Color::DarkGray),
+        "light_red"     => Some(Color::LightRed),
+        "light_green"   => Some(Color::LightGreen),
+        "light_yellow"  => Some(Color::LightYellow),
+        "light_blue"    => Some(Color::LightBlue),
+        "light_magenta" => Some(Color::LightMagenta),
+        "light_cyan"    => Some(Color::LightCyan),
+        "white"         => Some(Color::White),
+        _ => None,
+    }
+}
+
+// terminals that don't say they do truecolor get the closest colour of the 256 colour cube
+fn fit_to_terminal(color: Color) -> Color {
+    let truecolor = match std::env::var("COLORTERM") {
+        Ok(v) => v == "truecolor" || v == "24bit",
+        Err(_) => false,
+    };
+    return match color {
+        Color::Rgb(r, g, b) if !truecolor => {
+            // the cube's levels are 0, 95, 135, 175, 215 and 255
+            let level = |v: u8| if v < 48 { 0 } else if v < 115 { 1 } else { (v - 35) / 40 };
+            Color::Indexed(16 + 36 * level(r) + 6 * level(g) + level(b))
+        },
+        c => c,
+    }
+}
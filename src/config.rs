@@ -0,0 +1,269 @@
+#![allow(clippy::needless_return, clippy::single_match, clippy::len_zero)]
+
+use std::{io,fs};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use crate::player::effects::{self, Effects, Preset, MAX_EQ_DB};
+use crate::keymap::{self, Keymap, Scope};
+use crate::app::theme::{self, Theme};
+
+// Settings read from the config file, everything is optional
+pub struct Config {
+    pub audio_device: Option<String>, // name of the output device, as listed in the settings view
+    pub presets: Vec<Preset>, // the built in effect presets followed by the user's own
+    pub genre_presets: HashMap<String,String>, // genre to preset name
+    pub album_presets: HashMap<String,String>, // album to preset name
+    pub resume_min_length: f64, // songs at least this long (in seconds) remember where they were left
+    pub vim_mode: bool, // j/k/gg/G, counts, "/" finds and ":" commands
+    pub keymap: Keymap, // the defaults (and vim mode's keys) with keymap.json on top
+    pub themes: Vec<Theme>, // the built in ones followed by the ones in the themes directory
+    pub theme: Option<String>, // name of the theme to start with
+}
+
+// $XDG_CONFIG_HOME/rumu/config.json, or ~/.config/rumu/config.json
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var("XDG_CONFIG_HOME") {
+        Ok(v) if v.len() > 0 => PathBuf::from(v),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".config"),
+    };
+    return Some(base.join("rumu"));
+}
+
+// A missing config file is fine, one that can't be read or parsed is not
+pub fn load() -> Result<Config, io::Error> {
+    let mut config = Config {
+        audio_device: None,
+        presets: effects::builtin_presets(),
+        genre_presets: HashMap::new(),
+        album_presets: HashMap::new(),
+        resume_min_length: 1200.0,
+        vim_mode: false,
+        keymap: keymap::default_keymap(),
+        themes: theme::builtin_themes(),
+        theme: None,
+    };
+    let dir = match config_dir() {
+        Some(d) => d,
+        None => { return Ok(config); },
+    };
+    let path = dir.join("config.json");
+    match read_json(&path)? {
+        Some(parsed) => read_config(&mut config, &parsed, &path)?,
+        None => {},
+    }
+    if config.vim_mode {
+        keymap::bind_vim(&mut config.keymap);
+    }
+    load_keymap(&mut config.keymap, &dir.join("keymap.json"))?;
+    load_themes(&mut config.themes, &dir.join("themes"))?;
+    match &config.theme {
+        Some(t) if !config.themes.iter().any(|x| x.name == *t) => {
+            return Err(invalid(&path, &format!("there is no theme \"{}\"", t)));
+        },
+        _ => {},
+    }
+    return Ok(config);
+}
+
+fn read_config(config: &mut Config, parsed: &json::JsonValue, path: &Path) -> Result<(), io::Error> {
+    config.audio_device = read_string(parsed, "audio_device", path)?;
+    config.theme = read_string(parsed, "theme", path)?;
+    if !parsed["vim_mode"].is_null() {
+        config.vim_mode = match parsed["vim_mode"].as_bool() {
+            Some(v) => v,
+            None => { return Err(invalid(path, "vim_mode should be true or false")); },
+        };
+    }
+    if !parsed["resume_min_length"].is_null() {
+        config.resume_min_length = match parsed["resume_min_length"].as_f64() {
+            Some(v) if v >= 0.0 => v,
+            _ => { return Err(invalid(path, "resume_min_length should be a number of seconds")); },
+        };
+    }
+
+    // "presets": { "name": { "eq": [10 numbers], "balance": 0.0, "mono": false, "compressor": false } }
+    for (name, value) in parsed["presets"].entries() {
+        let preset = Preset {
+            name: String::from(name),
+            effects: read_effects(value).map_err(|e| invalid(path, &format!("preset \"{}\": {}", name, e)))?,
+        };
+        // a preset of the same name replaces the built in one
+        match config.presets.iter_mut().find(|p| p.name == name) {
+            Some(p) => { *p = preset; },
+            None => config.presets.push(preset),
+        }
+    }
+
+    // "genre_presets"/"album_presets": { "genre or album": "preset name" }
+    for (key, bindings) in [("genre_presets", &mut config.genre_presets), ("album_presets", &mut config.album_presets)] {
+        for (name, value) in parsed[key].entries() {
+            let preset = match value.as_str() {
+                Some(p) => p,
+                None => { return Err(invalid(path, &format!("{} \"{}\" should be a preset name", key, name))); },
+            };
+            if !config.presets.iter().any(|p| p.name == preset) {
+                return Err(invalid(path, &format!("{} \"{}\": there is no preset \"{}\"", key, name, preset)));
+            }
+            bindings.insert(String::from(name), String::from(preset));
+        }
+    }
+    return Ok(());
+}
+
+// None if the file isn't there
+fn read_json(path: &PathBuf) -> Result<Option<json::JsonValue>, io::Error> {
+    let content = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => { return Ok(None); },
+        Err(e) => { return Err(e); },
+    };
+    return match json::parse(&content) {
+        Ok(p) => Ok(Some(p)),
+        Err(e) => Err(invalid(path, &e.to_string())),
+    }
+}
+
+// { "global": { "ctrl+p": "play" }, "queue": { "x": "shuffle_remaining" } }
+fn load_keymap(keymap: &mut Keymap, path: &PathBuf) -> Result<(), io::Error> {
+    let parsed = match read_json(path)? {
+        Some(p) => p,
+        None => { return Ok(()); },
+    };
+    if !parsed.is_object() {
+        return Err(invalid(path, "should map panels to their keys"));
+    }
+    for (panel, keys) in parsed.entries() {
+        let scope : Scope = match panel.parse() {
+            Ok(s) => s,
+            Err(_) => {
+                return Err(invalid(path, &format!("unknown panel \"{}\", it should be global, songs, queue, navigator, search or settings", panel)));
+            },
+        };
+        if !keys.is_object() {
+            return Err(invalid(path, &format!("{} should map keys to actions", panel)));
+        }
+        for (chord, action) in keys.entries() {
+            let action = match action.as_str() {
+                Some(a) => a,
+                None => { return Err(invalid(path, &format!("{}: \"{}\" should be an action name", panel, chord))); },
+            };
+            keymap.bind(scope, chord, action).map_err(|e| invalid(path, &format!("{}: {}", panel, e)))?;
+        }
+    }
+    return keymap.validate().map_err(|e| invalid(path, &e));
+}
+
+// every <name>.json in the themes directory, a theme with the name of a built in one replaces it
+fn load_themes(themes: &mut Vec<Theme>, dir: &PathBuf) -> Result<(), io::Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => { return Ok(()); },
+        Err(e) => { return Err(e); },
+    };
+    let mut paths : Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == "json").unwrap_or(false))
+        .collect();
+    paths.sort();
+    let builtin = theme::builtin_themes();
+    for path in paths {
+        let parsed = match read_json(&path)? {
+            Some(p) => p,
+            None => continue,
+        };
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let theme = read_theme(&name, &parsed, &builtin).map_err(|e| invalid(&path, &e))?;
+        match themes.iter_mut().find(|t| t.name == name) {
+            Some(t) => { *t = theme; },
+            None => themes.push(theme),
+        }
+    }
+    return Ok(());
+}
+
+// { "base": "dark", "focused_border": "#ffaf00", "playing": "208", ... }, what's left out comes from the base
+fn read_theme(name: &str, value: &json::JsonValue, builtin: &[Theme]) -> Result<Theme, String> {
+    let base = match &value["base"] {
+        v if v.is_null() => "dark",
+        v => v.as_str().ok_or(String::from("base should be the name of a built in theme"))?,
+    };
+    let mut theme = match builtin.iter().find(|t| t.name == base) {
+        Some(t) => t.clone(),
+        None => { return Err(format!("there is no built in theme \"{}\" to base it on", base)); },
+    };
+    theme.name = String::from(name);
+    let fields = [
+        ("text", &mut theme.text),
+        ("background", &mut theme.background),
+        ("border", &mut theme.border),
+        ("focused_border", &mut theme.focused_border),
+        ("selected", &mut theme.selected),
+        ("selected_background", &mut theme.selected_background),
+        ("playing", &mut theme.playing),
+        ("broken", &mut theme.broken),
+        ("gauge", &mut theme.gauge),
+        ("gauge_background", &mut theme.gauge_background),
+        ("gauge_paused", &mut theme.gauge_paused),
+        ("markers", &mut theme.markers),
+    ];
+    for (key, _) in value.entries() {
+        if key != "base" && !fields.iter().any(|(k, _)| *k == key) {
+            return Err(format!("unknown setting \"{}\"", key));
+        }
+    }
+    for (key, field) in fields {
+        if value[key].is_null() {
+            continue;
+        }
+        *field = match value[key].as_str().and_then(theme::parse_color) {
+            Some(c) => c,
+            None => { return Err(format!("{} should be a colour name, a number from 0 to 255 or #rrggbb", key)); },
+        };
+    }
+    return Ok(theme);
+}
+
+fn invalid(path: &Path, message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+}
+
+fn read_effects(value: &json::JsonValue) -> Result<Effects, String> {
+    let mut effects = Effects::flat();
+    if !value["eq"].is_null() {
+        if !value["eq"].is_array() || value["eq"].len() != effects.eq.len() {
+            return Err(format!("eq should be a list of {} numbers", effects.eq.len()));
+        }
+        for (i, band) in value["eq"].members().enumerate() {
+            effects.eq[i] = match band.as_f64() {
+                Some(g) if g.abs() <= MAX_EQ_DB => g,
+                _ => { return Err(format!("eq values should be numbers from -{} to {}", MAX_EQ_DB, MAX_EQ_DB)); },
+            };
+        }
+    }
+    if !value["balance"].is_null() {
+        effects.balance = match value["balance"].as_f64() {
+            Some(b) if b.abs() <= 1.0 => b,
+            _ => { return Err(String::from("balance should be a number from -1 to 1")); },
+        };
+    }
+    for (key, field) in [("mono", &mut effects.mono), ("compressor", &mut effects.compressor)] {
+        if !value[key].is_null() {
+            *field = match value[key].as_bool() {
+                Some(b) => b,
+                None => { return Err(format!("{} should be true or false", key)); },
+            };
+        }
+    }
+    return Ok(effects);
+}
+
+fn read_string(parsed: &json::JsonValue, key: &str, path: &Path) -> Result<Option<String>, io::Error> {
+    let value = &parsed[key];
+    if value.is_null() {
+        return Ok(None);
+    }
+    return match value.as_str() {
+        Some(s) => Ok(Some(String::from(s))),
+        None => Err(invalid(path, &format!("{} should be a string", key))),
+    }
+}
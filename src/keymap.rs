@@ -0,0 +1,457 @@
+#![allow(clippy::needless_return, clippy::single_match, clippy::len_zero)]
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use crossterm::event::{KeyCode, KeyModifiers, KeyEvent};
+
+// Everything a key can do. The panel ones (up, down, ...) go to whichever panel is focused.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Nothing, // unbinds a default
+    Quit,
+    FocusSongs,
+    FocusQueue,
+    FocusSearch,
+    FocusSettings,
+    FocusNavigator,
+    Play,
+    TogglePause,
+    Previous,
+    ResumeSession,
+    ExportQueue,
+    Analyze,
+    AnalyzeAndTag,
+    SeekBack,
+    SeekForward,
+    SeekBackLong,
+    SeekForwardLong,
+    Jump(u8), // to a tenth of the song
+    VolumeUp,
+    VolumeDown,
+    ToggleMute,
+    CycleReplayGain,
+    PreampDown,
+    PreampUp,
+    CrossfadeDown,
+    CrossfadeUp,
+    SpeedDown,
+    SpeedUp,
+    SpeedReset,
+    TogglePitchCorrection,
+    CycleSpeedMemory,
+    LoopStart,
+    LoopEnd,
+    LoopSave,
+    LoopClear,
+    ResumePosition,
+    CycleStopAfter,
+    CycleSleepTimer,
+    ToggleSleepFade,
+    CycleShuffle,
+    ShuffleRemaining,
+    CycleRepeat,
+    Up,
+    Down,
+    Left,
+    Right,
+    Accept,
+    Back,
+    First,
+    Last,
+    HalfPageDown,
+    HalfPageUp,
+    Find,
+    FindNext,
+    FindPrevious,
+    CommandLine,
+    CycleTheme,
+}
+
+const ACTIONS : [(&str, Action); 67] = [
+    ("none", Action::Nothing),
+    ("quit", Action::Quit),
+    ("focus_songs", Action::FocusSongs),
+    ("focus_queue", Action::FocusQueue),
+    ("focus_search", Action::FocusSearch),
+    ("focus_settings", Action::FocusSettings),
+    ("focus_navigator", Action::FocusNavigator),
+    ("play", Action::Play),
+    ("toggle_pause", Action::TogglePause),
+    ("previous", Action::Previous),
+    ("resume_session", Action::ResumeSession),
+    ("export_queue", Action::ExportQueue),
+    ("analyze", Action::Analyze),
+    ("analyze_and_tag", Action::AnalyzeAndTag),
+    ("seek_back", Action::SeekBack),
+    ("seek_forward", Action::SeekForward),
+    ("seek_back_long", Action::SeekBackLong),
+    ("seek_forward_long", Action::SeekForwardLong),
+    ("jump_0", Action::Jump(0)),
+    ("jump_10", Action::Jump(1)),
+    ("jump_20", Action::Jump(2)),
+    ("jump_30", Action::Jump(3)),
+    ("jump_40", Action::Jump(4)),
+    ("jump_50", Action::Jump(5)),
+    ("jump_60", Action::Jump(6)),
+    ("jump_70", Action::Jump(7)),
+    ("jump_80", Action::Jump(8)),
+    ("jump_90", Action::Jump(9)),
+    ("volume_up", Action::VolumeUp),
+    ("volume_down", Action::VolumeDown),
+    ("toggle_mute", Action::ToggleMute),
+    ("cycle_replaygain", Action::CycleReplayGain),
+    ("preamp_down", Action::PreampDown),
+    ("preamp_up", Action::PreampUp),
+    ("crossfade_down", Action::CrossfadeDown),
+    ("crossfade_up", Action::CrossfadeUp),
+    ("speed_down", Action::SpeedDown),
+    ("speed_up", Action::SpeedUp),
+    ("speed_reset", Action::SpeedReset),
+    ("toggle_pitch_correction", Action::TogglePitchCorrection),
+    ("cycle_speed_memory", Action::CycleSpeedMemory),
+    ("loop_start", Action::LoopStart),
+    ("loop_end", Action::LoopEnd),
+    ("loop_save", Action::LoopSave),
+    ("loop_clear", Action::LoopClear),
+    ("resume_position", Action::ResumePosition),
+    ("cycle_stop_after", Action::CycleStopAfter),
+    ("cycle_sleep_timer", Action::CycleSleepTimer),
+    ("toggle_sleep_fade", Action::ToggleSleepFade),
+    ("cycle_shuffle", Action::CycleShuffle),
+    ("shuffle_remaining", Action::ShuffleRemaining),
+    ("cycle_repeat", Action::CycleRepeat),
+    ("up", Action::Up),
+    ("down", Action::Down),
+    ("left", Action::Left),
+    ("right", Action::Right),
+    ("accept", Action::Accept),
+    ("back", Action::Back),
+    ("first", Action::First),
+    ("last", Action::Last),
+    ("half_page_down", Action::HalfPageDown),
+    ("half_page_up", Action::HalfPageUp),
+    ("find", Action::Find),
+    ("find_next", Action::FindNext),
+    ("find_previous", Action::FindPrevious),
+    ("command_line", Action::CommandLine),
+    ("cycle_theme", Action::CycleTheme),
+];
+
+impl FromStr for Action {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Action, ()> {
+        return ACTIONS.iter().find(|(name, _)| *name == s).map(|(_, a)| *a).ok_or(());
+    }
+}
+
+// Where a binding applies. Bindings of the focused panel come before the global ones.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Scope {
+    Global,
+    Songs,
+    Queue,
+    Navigator,
+    Search,
+    Settings,
+}
+
+impl FromStr for Scope {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Scope, ()> {
+        return match s {
+            "global"    => Ok(Scope::Global),
+            "songs"     => Ok(Scope::Songs),
+            "queue"     => Ok(Scope::Queue),
+            "navigator" => Ok(Scope::Navigator),
+            "search"    => Ok(Scope::Search),
+            "settings"  => Ok(Scope::Settings),
+            _ => Err(()),
+        }
+    }
+}
+
+// a key with its modifiers. shift isn't kept for characters, 'S' already says it
+pub type Chord = (KeyModifiers, KeyCode);
+
+pub fn chord(event: &KeyEvent) -> Chord {
+    return match event.code {
+        KeyCode::Char(c) => (event.modifiers - KeyModifiers::SHIFT, KeyCode::Char(c)),
+        KeyCode::BackTab => (event.modifiers - KeyModifiers::SHIFT, KeyCode::BackTab),
+        code => (event.modifiers, code),
+    }
+}
+
+// "ctrl+p", "alt+left", "shift+tab", "S", "space", "alt++"
+pub fn parse_chord(s: &str) -> Result<Chord, String> {
+    let mut modifiers = KeyModifiers::NONE;
+    // a trailing "+" after a "+" is the plus key
+    let (prefix, key) = match s.strip_suffix("++") {
+        Some(p) => (format!("{}+", p), "+"),
+        None => match s.rfind('+') {
+            Some(i) if i + 1 < s.len() => (String::from(&s[..i + 1]), &s[i + 1..]),
+            _ => (String::new(), s),
+        },
+    };
+    for modifier in prefix.split('+').filter(|m| m.len() > 0) {
+        modifiers |= match modifier {
+            "ctrl"  => KeyModifiers::CONTROL,
+            "alt"   => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => { return Err(format!("unknown modifier \"{}\" in \"{}\"", modifier, s)); },
+        };
+    }
+    let code = match key {
+        "space"     => KeyCode::Char(' '),
+        "tab"       => KeyCode::Tab,
+        "enter"     => KeyCode::Enter,
+        "esc"       => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "delete"    => KeyCode::Delete,
+        "insert"    => KeyCode::Insert,
+        "up"        => KeyCode::Up,
+        "down"      => KeyCode::Down,
+        "left"      => KeyCode::Left,
+        "right"     => KeyCode::Right,
+        "home"      => KeyCode::Home,
+        "end"       => KeyCode::End,
+        "pageup"    => KeyCode::PageUp,
+        "pagedown"  => KeyCode::PageDown,
+        k if k.chars().count() == 1 => KeyCode::Char(k.chars().next().unwrap()),
+        k => match k.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+            _ => { return Err(format!("unknown key \"{}\" in \"{}\"", k, s)); },
+        },
+    };
+    return match code {
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+            Ok((modifiers - KeyModifiers::SHIFT, KeyCode::Char(c.to_ascii_uppercase())))
+        },
+        // shift+tab comes in as its own key
+        KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => Ok((modifiers - KeyModifiers::SHIFT, KeyCode::BackTab)),
+        _ => Ok((modifiers, code)),
+    }
+}
+
+// what a key (or the keys typed before it) does
+pub enum Lookup {
+    Action(Action),
+    Prefix, // the start of a longer binding like "g g", wait for the next key
+    Unbound,
+}
+
+pub struct Keymap {
+    bindings: HashMap<(Scope, Vec<Chord>), Action>,
+}
+
+impl Keymap {
+    // bindings of the panel come first. typing in the search bar isn't taken by plain global keys
+    pub fn get(&self, scope: Scope, keys: &[Chord]) -> Lookup {
+        let typed = scope == Scope::Search && matches!(keys.first(), Some((KeyModifiers::NONE, KeyCode::Char(_))));
+        let scopes = if typed { vec![scope] } else { vec![scope, Scope::Global] };
+        for s in scopes.iter() {
+            match self.bindings.get(&(*s, keys.to_vec())) {
+                Some(Action::Nothing) => { return Lookup::Unbound; },
+                Some(a) => { return Lookup::Action(*a); },
+                None => {},
+            }
+        }
+        let prefix = self.bindings.iter().any(|((s, k), a)| {
+            scopes.contains(s) && *a != Action::Nothing && k.len() > keys.len() && k.starts_with(keys)
+        });
+        return if prefix { Lookup::Prefix } else { Lookup::Unbound };
+    }
+
+    // keys is one chord or several separated by spaces, "ctrl+p" or "g g"
+    pub fn bind(&mut self, scope: Scope, keys: &str, action: &str) -> Result<(), String> {
+        let mut parsed : Vec<Chord> = Vec::new();
+        for chord in keys.split_whitespace() {
+            parsed.push(parse_chord(chord)?);
+        }
+        if parsed.len() == 0 {
+            return Err(String::from("a binding needs a key"));
+        }
+        let action = match action.parse() {
+            Ok(a) => a,
+            Err(_) => { return Err(format!("\"{}\": unknown action \"{}\"", keys, action)); },
+        };
+        self.bindings.insert((scope, parsed), action);
+        return Ok(());
+    }
+
+    // a keymap that can't quit would leave the terminal stuck in rumu, one that can't switch
+    // panels would leave it stuck in one of them. a binding that is the start of a longer one in
+    // the same panel would never let the longer one be typed
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.bindings.iter().any(|((s, _), a)| *s == Scope::Global && *a == Action::Quit) {
+            return Err(String::from("no global key is bound to quit"));
+        }
+        let switches_panels = self.bindings.iter().any(|((s, _), a)| *s == Scope::Global && match a {
+            Action::FocusSongs | Action::FocusQueue | Action::FocusSearch | Action::FocusSettings | Action::FocusNavigator => true,
+            // every action works as a command there
+            Action::CommandLine => true,
+            _ => false,
+        });
+        if !switches_panels {
+            return Err(String::from("no global key is bound to a focus_ action, there would be no way to switch panels"));
+        }
+        for ((scope, keys), action) in self.bindings.iter() {
+            if *action == Action::Nothing {
+                continue;
+            }
+            match self.bindings.iter().find(|((s, k), a)| s == scope && **a != Action::Nothing && k.len() > keys.len() && k.starts_with(keys)) {
+                Some(((_, longer), _)) => {
+                    return Err(format!("{}: \"{}\" is the start of \"{}\", so it could never be typed",
+                        format!("{:?}", scope).to_lowercase(), keys_name(keys), keys_name(longer)));
+                },
+                None => {},
+            }
+        }
+        return Ok(());
+    }
+}
+
+// the keys of a binding the way they're written in keymap.json
+fn keys_name(keys: &[Chord]) -> String {
+    let names : Vec<String> = keys.iter().map(|(modifiers, code)| {
+        let mut name = String::new();
+        if modifiers.contains(KeyModifiers::CONTROL) {
+            name.push_str("ctrl+");
+        }
+        if modifiers.contains(KeyModifiers::ALT) {
+            name.push_str("alt+");
+        }
+        match code {
+            KeyCode::Char(' ') => name.push_str("space"),
+            KeyCode::Char(c) => name.push(*c),
+            KeyCode::BackTab => name.push_str("shift+tab"),
+            KeyCode::F(n) => name.push_str(&format!("f{}", n)),
+            c => name.push_str(&format!("{:?}", c).to_lowercase()),
+        }
+        return name;
+    }).collect();
+    return names.join(" ");
+}
+
+// the keys listed in the README
+pub fn default_keymap() -> Keymap {
+    let mut keymap = Keymap { bindings: HashMap::new() };
+    let defaults : [(Scope, &str, &str); 58] = [
+        (Scope::Global, "esc", "quit"),
+        (Scope::Global, "tab", "focus_navigator"),
+        (Scope::Global, "ctrl+w", "focus_songs"),
+        (Scope::Global, "ctrl+q", "focus_queue"),
+        (Scope::Global, "ctrl+s", "focus_search"),
+        (Scope::Global, "ctrl+d", "focus_settings"),
+        (Scope::Global, "ctrl+p", "play"),
+        (Scope::Global, "ctrl+space", "toggle_pause"),
+        (Scope::Global, "ctrl+left", "seek_back"),
+        (Scope::Global, "ctrl+right", "seek_forward"),
+        (Scope::Global, "alt+left", "seek_back_long"),
+        (Scope::Global, "alt+right", "seek_forward_long"),
+        (Scope::Global, "alt+0", "jump_0"),
+        (Scope::Global, "alt+1", "jump_10"),
+        (Scope::Global, "alt+2", "jump_20"),
+        (Scope::Global, "alt+3", "jump_30"),
+        (Scope::Global, "alt+4", "jump_40"),
+        (Scope::Global, "alt+5", "jump_50"),
+        (Scope::Global, "alt+6", "jump_60"),
+        (Scope::Global, "alt+7", "jump_70"),
+        (Scope::Global, "alt+8", "jump_80"),
+        (Scope::Global, "alt+9", "jump_90"),
+        (Scope::Global, "ctrl+up", "volume_up"),
+        (Scope::Global, "ctrl+down", "volume_down"),
+        (Scope::Global, "alt+m", "toggle_mute"),
+        (Scope::Global, "alt+g", "cycle_replaygain"),
+        (Scope::Global, "alt+,", "preamp_down"),
+        (Scope::Global, "alt+.", "preamp_up"),
+        (Scope::Global, "alt+[", "speed_down"),
+        (Scope::Global, "alt+]", "speed_up"),
+        (Scope::Global, "alt+\\", "speed_reset"),
+        (Scope::Global, "alt+p", "toggle_pitch_correction"),
+        (Scope::Global, "alt+o", "cycle_speed_memory"),
+        (Scope::Global, "alt+-", "crossfade_down"),
+        (Scope::Global, "alt+=", "crossfade_up"),
+        (Scope::Global, "alt+a", "loop_start"),
+        (Scope::Global, "alt+b", "loop_end"),
+        (Scope::Global, "alt+l", "loop_save"),
+        (Scope::Global, "alt+x", "loop_clear"),
+        (Scope::Global, "alt+r", "resume_position"),
+        (Scope::Global, "alt+t", "cycle_stop_after"),
+        (Scope::Global, "alt+z", "cycle_sleep_timer"),
+        (Scope::Global, "alt+f", "toggle_sleep_fade"),
+        (Scope::Global, "alt+c", "cycle_theme"),
+        (Scope::Global, "ctrl+b", "previous"),
+        (Scope::Global, "ctrl+r", "resume_session"),
+        (Scope::Global, "ctrl+e", "export_queue"),
+        (Scope::Global, "ctrl+g", "analyze"),
+        (Scope::Global, "ctrl+t", "analyze_and_tag"),
+        (Scope::Global, "up", "up"),
+        (Scope::Global, "down", "down"),
+        (Scope::Global, "left", "left"),
+        (Scope::Global, "right", "right"),
+        (Scope::Global, "enter", "accept"),
+        (Scope::Global, "backspace", "back"),
+        (Scope::Queue, "s", "cycle_shuffle"),
+        (Scope::Queue, "S", "shuffle_remaining"),
+        (Scope::Queue, "r", "cycle_repeat"),
+    ];
+    for (scope, chord, action) in defaults {
+        keymap.bind(scope, chord, action).unwrap();
+    }
+    return keymap;
+}
+
+// what vim mode adds on top of the defaults, counts like "5j" are handled by the app
+pub fn bind_vim(keymap: &mut Keymap) {
+    let bindings : [(&str, &str); 10] = [
+        ("j", "down"),
+        ("k", "up"),
+        ("g g", "first"),
+        ("G", "last"),
+        ("ctrl+d", "half_page_down"),
+        ("ctrl+u", "half_page_up"),
+        ("/", "find"),
+        ("n", "find_next"),
+        ("N", "find_previous"),
+        (":", "command_line"),
+    ];
+    for (chord, action) in bindings {
+        keymap.bind(Scope::Global, chord, action).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        let mut keymap = default_keymap();
+        assert_eq!(keymap.validate(), Ok(()));
+        bind_vim(&mut keymap);
+        assert_eq!(keymap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn prefix_of_a_longer_binding_is_rejected() {
+        let mut keymap = default_keymap();
+        bind_vim(&mut keymap);
+        keymap.bind(Scope::Global, "g", "play").unwrap();
+        assert_eq!(keymap.validate(), Err(String::from("global: \"g\" is the start of \"g g\", so it could never be typed")));
+        // fine once it's unbound again, or when it's only in a panel
+        keymap.bind(Scope::Global, "g", "none").unwrap();
+        keymap.bind(Scope::Queue, "g", "play").unwrap();
+        assert_eq!(keymap.validate(), Ok(()));
+    }
+
+    #[test]
+    fn panels_have_to_be_reachable() {
+        let mut keymap = default_keymap();
+        for keys in ["tab", "ctrl+w", "ctrl+q", "ctrl+s", "ctrl+d"] {
+            keymap.bind(Scope::Global, keys, "none").unwrap();
+        }
+        assert!(keymap.validate().is_err());
+        keymap.bind(Scope::Global, ":", "command_line").unwrap();
+        assert_eq!(keymap.validate(), Ok(()));
+    }
+}
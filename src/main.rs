@@ -1,15 +1,29 @@
-use std::{thread, time::Duration};
-
 mod songdb;
 mod app;
 mod player;
 mod song;
+mod xspf;
+mod config;
+mod keymap;
 
+#[allow(clippy::manual_unwrap_or, clippy::manual_unwrap_or_default, clippy::single_match)]
 fn main() {
     let args : Vec<String> = std::env::args().collect();
     println!("Hello, I'm making rumu!");
 
-    let mut player = player::new();
+    let config = match config::load() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("can't load config: {}", e);
+            return;
+        },
+    };
+
+    // RUMU_PLAYER=null runs without any audio, RUMU_PLAYER=native without mpv
+    let backend = std::env::var("RUMU_PLAYER").ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default();
+    let player = player::new(backend);
     /*
     player.play(&args[1]);
     player.is_song_finished();
@@ -45,9 +59,12 @@ fn main() {
     // look through database and remove any songs that don't exist in fs
     match db.prune_db() {
         Ok(_) => (),
-        Err(e) => {} //println!("{}",e),
+        Err(_) => {} //println!("{}",e),
     }; 
 
-    let mut app = app::create(db);
+    // optionally start with a playlist in the queue
+    let playlist = args.get(2).cloned();
+
+    let mut app = app::create(db, player, config, playlist);
     let _ = app.start();
 }
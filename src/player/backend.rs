@@ -0,0 +1,47 @@
+use crate::player::FinishReason;
+use crate::player::effects::Effects;
+
+// what a backend reports while playing
+pub enum BackendEvent {
+    Loaded(String), // path of the song that's loaded and playing
+    // bad_file is for when the file itself couldn't be opened or decoded, not the output
+    Ended { reason: FinishReason, error: Option<String>, bad_file: bool },
+    Idle, // nothing left to play
+}
+
+// somewhere sound can go
+#[derive(Clone, PartialEq)]
+pub struct AudioDevice {
+    pub name: String, // what the backend knows it by
+    pub description: String,
+}
+
+// Something that can play songs. The player thread drives one of these (two during a crossfade),
+// so everything above it doesn't care what actually makes the sound.
+pub trait PlayerBackend {
+    // stop what's playing and play path
+    fn load(&mut self, path: &str) -> Result<(),String>;
+    // play path once the current song ends, without a gap
+    fn append(&mut self, path: &str) -> Result<(),String>;
+    // forget everything that was appended
+    fn clear_appended(&mut self) -> Result<(),String>;
+    fn stop(&mut self) -> Result<(),String>;
+    fn set_pause(&mut self, pause: bool) -> Result<(),String>;
+    fn seek(&mut self, seconds: f64) -> Result<(),String>; // relative, negative goes back
+    fn seek_to(&mut self, seconds: f64) -> Result<(),String>;
+    fn set_volume(&mut self, volume: f64) -> Result<(),String>; // in percent
+    fn set_mute(&mut self, mute: bool) -> Result<(),String>;
+    // loudness correction for the current song, in dB
+    fn set_gain(&mut self, gain: f64) -> Result<(),String>;
+    // eq, balance and so on. they stay on between songs
+    fn set_effects(&mut self, effects: &Effects) -> Result<(),String>;
+    // 1.0 is normal speed. pitch_correction keeps the pitch the same at other speeds
+    fn set_speed(&mut self, speed: f64, pitch_correction: bool) -> Result<(),String>;
+    fn get_devices(&self) -> Vec<AudioDevice>;
+    // switch the output over while playing
+    fn set_device(&mut self, name: &str) -> Result<(),String>;
+    fn get_time_pos(&self) -> Option<f64>;
+    fn get_duration(&self) -> Option<f64>;
+    // waits up to timeout seconds for something to happen
+    fn wait_event(&mut self, timeout: f64) -> Option<BackendEvent>;
+}
@@ -0,0 +1,52 @@
+// centre frequencies of the equalizer bands, in Hz
+pub const EQ_BANDS : [f64; 10] = [31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0];
+
+// how far a band can be boosted or cut, in dB
+pub const MAX_EQ_DB : f64 = 12.0;
+
+// Everything done to the sound besides volume and replaygain
+#[derive(Clone, PartialEq)]
+pub struct Effects {
+    pub eq: [f64; EQ_BANDS.len()], // gain per band in dB
+    pub balance: f64,  // -1.0 is all left, 1.0 all right
+    pub mono: bool,    // mix both channels down to one
+    pub compressor: bool, // evens out loud and quiet parts
+}
+
+impl Effects {
+    pub fn flat() -> Effects {
+        return Effects {
+            eq: [0.0; EQ_BANDS.len()],
+            balance: 0.0,
+            mono: false,
+            compressor: false,
+        }
+    }
+}
+
+// effects under a name the user can pick
+#[derive(Clone)]
+pub struct Preset {
+    pub name: String,
+    pub effects: Effects,
+}
+
+fn preset(name: &str, eq: [f64; 10], mono: bool, compressor: bool) -> Preset {
+    return Preset {
+        name: String::from(name),
+        effects: Effects { eq, balance: 0.0, mono, compressor },
+    }
+}
+
+// what's there without a config file, "flat" is always first
+pub fn builtin_presets() -> Vec<Preset> {
+    return vec![
+        preset("flat",         [0.0; 10], false, false),
+        preset("bass boost",   [6.0, 5.0, 4.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0], false, false),
+        preset("treble boost", [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 2.0, 4.0, 5.0, 6.0], false, false),
+        preset("vocal",        [-2.0, -2.0, -1.0, 0.0, 2.0, 3.0, 3.0, 2.0, 0.0, -1.0], false, false),
+        preset("loudness",     [5.0, 4.0, 2.0, 0.0, -1.0, 0.0, 0.0, 1.0, 3.0, 4.0], false, false),
+        preset("night",        [0.0; 10], false, true),
+        preset("mono",         [0.0; 10], true, false),
+    ];
+}
@@ -0,0 +1,1024 @@
+#![allow(clippy::needless_return, clippy::single_match, clippy::len_zero)]
+
+use std::mem;
+use std::thread;
+use std::sync::mpsc::{self, Sender, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+use std::str::FromStr;
+use std::collections::HashMap;
+
+use crate::song::Song;
+use crate::player::backend::{PlayerBackend, BackendEvent, AudioDevice};
+use crate::player::effects::Effects;
+
+pub mod backend;
+pub mod effects;
+#[cfg(feature = "mpv")]
+mod mpvbackend;
+#[cfg(feature = "native")]
+mod nativebackend;
+#[cfg(feature = "native")]
+mod sink;
+mod nullbackend;
+
+// mpv's default volume-max
+const MAX_VOLUME : f64 = 130.0;
+
+// playback speed range
+const MIN_SPEED : f64 = 0.5;
+const MAX_SPEED : f64 = 2.0;
+
+// how long the player thread waits for mpv before checking for commands again
+const EVENT_WAIT_SECS : f64 = 0.02;
+
+// how often the position of the current song is sent
+const POSITION_INTERVAL : Duration = Duration::from_millis(100);
+
+// how long songs pretend to be with the null backend
+const NULL_SONG_LENGTH : f64 = 180.0;
+
+// what plays the songs
+#[derive(Clone, Copy, PartialEq)]
+pub enum Backend {
+    #[cfg(feature = "mpv")]
+    Mpv,
+    #[cfg(feature = "native")]
+    Native, // decodes and plays by itself, without mpv
+    Null, // plays nothing, for running without audio
+}
+
+// mpv when it's built in
+impl Default for Backend {
+    #[allow(unreachable_code)]
+    fn default() -> Backend {
+        #[cfg(feature = "mpv")]
+        return Backend::Mpv;
+        #[cfg(feature = "native")]
+        return Backend::Native;
+        return Backend::Null;
+    }
+}
+
+impl FromStr for Backend {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Backend, ()> {
+        return match s {
+            #[cfg(feature = "mpv")]
+            "mpv"  => Ok(Backend::Mpv),
+            #[cfg(feature = "native")]
+            "native" => Ok(Backend::Native),
+            "null" => Ok(Backend::Null),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(feature = "mpv")]
+fn make_mpv() -> Box<dyn PlayerBackend> {
+    return Box::new(mpvbackend::new());
+}
+
+#[cfg(feature = "native")]
+fn make_native() -> Box<dyn PlayerBackend> {
+    return Box::new(nativebackend::new());
+}
+
+fn make_null() -> Box<dyn PlayerBackend> {
+    return Box::new(nullbackend::new(NULL_SONG_LENGTH, HashMap::new(), nullbackend::Clock::Real));
+}
+
+// called on the player thread whenever it needs a backend
+pub type MakeBackend = Box<dyn Fn() -> Box<dyn PlayerBackend> + Send>;
+
+// which replaygain tags are used
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReplayGain {
+    Off,
+    Track,
+    Album,
+    Auto, // album gain while an album is playing through, track gain otherwise
+}
+
+#[allow(clippy::to_string_trait_impl)]
+impl ToString for ReplayGain {
+    fn to_string(&self) -> String {
+        return match self {
+            ReplayGain::Off   => String::from("off"),
+            ReplayGain::Track => String::from("track"),
+            ReplayGain::Album => String::from("album"),
+            ReplayGain::Auto  => String::from("auto"),
+        }
+    }
+}
+
+impl FromStr for ReplayGain {
+    type Err = ();
+    fn from_str(s: &str) -> Result<ReplayGain, ()> {
+        return match s {
+            "off"   => Ok(ReplayGain::Off),
+            "track" => Ok(ReplayGain::Track),
+            "album" => Ok(ReplayGain::Album),
+            "auto"  => Ok(ReplayGain::Auto),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FinishReason {
+    Eof,     // played until the end
+    Stopped, // stopped or replaced by another song
+    Error,   // couldn't be played
+}
+
+// what the player thread tells the app
+pub enum PlayerEvent {
+    Started(String), // path of the song that started
+    Paused(bool),
+    Position { time: f64, duration: f64 }, // in seconds
+    // next is the preloaded song mpv moved on to by itself, if any
+    Finished { reason: FinishReason, next: Option<String> },
+    Error(String),
+    Unplayable(String), // path of a song that couldn't be loaded
+    Idle, // nothing left to play
+    Devices(Vec<AudioDevice>),
+    SpeedRejected { speed: f64, pitch_correction: bool }, // what keeps playing instead
+}
+
+// what the app tells the player thread
+enum PlayerCommand {
+    Play(String, Option<f64>), // path, where to start
+    Crossfade(String, f64),    // path, seconds
+    Preload(Option<String>),
+    Stop,
+    Pause,
+    Resume,
+    Seek(f64),
+    SeekTo(f64),
+    SetVolume(f64),
+    SetMute(bool),
+    SetGain(String, f64), // path, dB
+    SetSpeed(f64, bool),  // speed, pitch correction
+    ListDevices,
+    SetDevice(String),
+    SetEffects(Effects),
+    SetLoop(Option<(f64, f64)>), // start, end in seconds
+}
+
+// The app's side of the player. Commands are sent to the player thread and what it reports back
+// is kept here, so reading the state never waits on the backend.
+pub struct Player {
+    commands: Sender<PlayerCommand>,
+    events: Receiver<PlayerEvent>,
+    time_pos: f64,
+    duration: f64,
+    paused: bool,
+    idle: bool, // nothing is playing, until the next song starts
+    volume: f64,
+    volume_scale: f64, // for fading out, on top of the volume
+    muted: bool,
+    preloaded: Option<String>, // last preload sent, so it isn't sent again every frame
+    fade_until: Option<Instant>,
+    replaygain: ReplayGain,
+    preamp: f64, // in dB, added to the replaygain
+    gains: HashMap<String,f64>, // gains sent per path
+    speed: f64,
+    pitch_correction: bool,
+    device: String,
+    effects: Effects,
+    ab_loop: Option<(f64, f64)>,
+}
+
+pub fn new(backend: Backend) -> Player {
+    let make_backend : fn() -> Box<dyn PlayerBackend> = match backend {
+        #[cfg(feature = "mpv")]
+        Backend::Mpv => make_mpv,
+        #[cfg(feature = "native")]
+        Backend::Native => make_native,
+        Backend::Null => make_null,
+    };
+    return with_backend(Box::new(make_backend));
+}
+
+pub fn with_backend(make_backend: MakeBackend) -> Player {
+    let (command_sender, command_receiver) = mpsc::channel();
+    let (event_sender, event_receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // mpv handles can't be moved between threads, so backends are made in here
+        let mut engine = Engine::new(make_backend, event_sender);
+        engine.run(command_receiver);
+    });
+
+    let player = Player {
+        commands: command_sender,
+        events: event_receiver,
+        time_pos: 0.0,
+        duration: 0.0,
+        paused: false,
+        idle: true,
+        volume: 100.0,
+        volume_scale: 1.0,
+        muted: false,
+        preloaded: None,
+        fade_until: None,
+        replaygain: ReplayGain::Off,
+        preamp: 0.0,
+        gains: HashMap::new(),
+        speed: 1.0,
+        pitch_correction: true,
+        device: String::from("auto"),
+        effects: Effects::flat(),
+        ab_loop: None,
+    };
+
+    return player;
+}
+
+impl Player {
+
+    fn send(&self, command: PlayerCommand) -> Result<(),&str> {
+        match self.commands.send(command) {
+            Ok(_) => Ok(()),
+            Err(_) => Err("player stopped"),
+        }
+    }
+
+    // forget everything about the current song
+    fn reset(&mut self) {
+        self.time_pos = 0.0;
+        self.duration = 0.0;
+        self.paused = false;
+        self.preloaded = None;
+        self.fade_until = None;
+        self.ab_loop = None;
+    }
+
+    // take in what the player thread reported since the last call
+    pub fn poll(&mut self) -> Vec<PlayerEvent> {
+        let events : Vec<PlayerEvent> = self.events.try_iter().collect();
+        for event in &events {
+            match event {
+                PlayerEvent::Position { time, duration } => {
+                    self.time_pos = *time;
+                    self.duration = *duration;
+                },
+                PlayerEvent::Paused(p) => { self.paused = *p; },
+                // loops belong to the song they were set on
+                PlayerEvent::Started(_) => {
+                    self.ab_loop = None;
+                    self.time_pos = 0.0;
+                    self.idle = false;
+                },
+                // whatever was preloaded is playing now or gone
+                PlayerEvent::Finished { .. } => { self.preloaded = None; },
+                // set_speed() guessed, the backend didn't take it
+                PlayerEvent::SpeedRejected { speed, pitch_correction } => {
+                    self.speed = *speed;
+                    self.pitch_correction = *pitch_correction;
+                },
+                PlayerEvent::Idle => {
+                    self.time_pos = 0.0;
+                    self.duration = 0.0;
+                    self.idle = true;
+                },
+                _ => {},
+            }
+        }
+        return events;
+    }
+
+    pub fn play(&mut self, path: &str) -> Result<(),&str> {
+        self.reset();
+        return self.send(PlayerCommand::Play(String::from(path), None));
+    }
+
+    // play a song starting some seconds in
+    pub fn play_from(&mut self, path: &str, seconds: f64) -> Result<(),&str> {
+        self.reset();
+        return self.send(PlayerCommand::Play(String::from(path), Some(seconds)));
+    }
+
+    // start playing path while the current song fades out over the given seconds
+    pub fn crossfade(&mut self, path: &str, seconds: f64) -> Result<(),&str> {
+        self.reset();
+        self.fade_until = Some(Instant::now() + Duration::from_secs_f64(seconds));
+        return self.send(PlayerCommand::Crossfade(String::from(path), seconds));
+    }
+
+    pub fn is_crossfading(&self) -> bool {
+        return match self.fade_until {
+            Some(t) => Instant::now() < t,
+            None => false,
+        }
+    }
+
+    pub fn pause(&mut self) -> Result<(),&str> {
+        self.paused = true;
+        self.fade_until = None;
+        return self.send(PlayerCommand::Pause);
+    }
+
+    pub fn resume(&mut self) -> Result<(),&str> {
+        self.paused = false;
+        return self.send(PlayerCommand::Resume);
+    }
+
+    pub fn toggle_pause(&mut self) -> Result<(),&str> {
+        if self.is_paused() {
+            return self.resume();
+        }
+        return self.pause();
+    }
+
+    pub fn is_idle(&self) -> bool {
+        return self.idle;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return self.paused;
+    }
+
+    // volume in percent, 100 is the file's own loudness
+    pub fn set_volume(&mut self, volume: f64) -> Result<(),&str> {
+        self.volume = volume.clamp(0.0, MAX_VOLUME);
+        return self.send(PlayerCommand::SetVolume(self.volume * self.volume_scale));
+    }
+
+    pub fn change_volume(&mut self, change: f64) -> Result<(),&str> {
+        let volume = self.get_volume() + change;
+        return self.set_volume(volume);
+    }
+
+    pub fn get_volume(&self) -> f64 {
+        return self.volume;
+    }
+
+    // Turn the volume down to a fraction of what's set without changing the setting itself,
+    // 1.0 plays at the set volume
+    pub fn set_volume_scale(&mut self, scale: f64) -> Result<(),&str> {
+        let scale = scale.clamp(0.0, 1.0);
+        if scale == self.volume_scale {
+            return Ok(());
+        }
+        self.volume_scale = scale;
+        return self.send(PlayerCommand::SetVolume(self.volume * self.volume_scale));
+    }
+
+    pub fn set_mute(&mut self, mute: bool) -> Result<(),&str> {
+        self.muted = mute;
+        return self.send(PlayerCommand::SetMute(mute));
+    }
+
+    pub fn toggle_mute(&mut self) -> Result<(),&str> {
+        let mute = !self.is_muted();
+        return self.set_mute(mute);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        return self.muted;
+    }
+
+    pub fn set_replaygain(&mut self, replaygain: ReplayGain) {
+        self.replaygain = replaygain;
+        self.gains.clear(); // so apply_replaygain sends them again
+    }
+
+    pub fn get_replaygain(&self) -> ReplayGain {
+        return self.replaygain;
+    }
+
+    pub fn set_preamp(&mut self, preamp: f64) {
+        self.preamp = preamp;
+        self.gains.clear();
+    }
+
+    pub fn get_preamp(&self) -> f64 {
+        return self.preamp;
+    }
+
+    // Make sure the song is played with its replaygain. Call it for the current and upcoming
+    // songs, the gain is only sent when it changes. in_album is whether an album is playing
+    // through, which the auto mode uses album gain for.
+    pub fn apply_replaygain(&mut self, song: &Song, in_album: bool) {
+        let use_album = match self.replaygain {
+            ReplayGain::Off => { return self.set_gain(&song.path, 0.0); },
+            ReplayGain::Track => false,
+            ReplayGain::Album => true,
+            ReplayGain::Auto => in_album,
+        };
+        let (gain, peak) = if use_album {
+            (song.album_gain.or(song.track_gain), song.album_peak.or(song.track_peak))
+        } else {
+            (song.track_gain, song.track_peak)
+        };
+        let mut gain = match gain {
+            Some(g) => g + self.preamp,
+            None => 0.0, // untagged songs are left alone
+        };
+        // don't push the loudest sample past full scale
+        match peak {
+            Some(p) if p > 0.0 => { gain = gain.min(-20.0 * p.log10()); },
+            _ => {},
+        }
+        self.set_gain(&song.path, gain);
+    }
+
+    fn set_gain(&mut self, path: &str, gain: f64) {
+        if self.gains.get(path) == Some(&gain) {
+            return;
+        }
+        self.gains.insert(String::from(path), gain);
+        self.send(PlayerCommand::SetGain(String::from(path), gain)).ok();
+    }
+
+    // 1.0 is normal speed, it stays the same between songs. get_speed() has the new speed right
+    // away, a PlayerEvent::SpeedRejected from poll() puts the old one back if the backend refuses
+    pub fn set_speed(&mut self, speed: f64) -> Result<(),&str> {
+        // rounded so stepping through speeds doesn't drift
+        self.speed = (speed.clamp(MIN_SPEED, MAX_SPEED) * 100.0).round() / 100.0;
+        return self.send(PlayerCommand::SetSpeed(self.speed, self.pitch_correction));
+    }
+
+    pub fn change_speed(&mut self, change: f64) -> Result<(),&str> {
+        let speed = self.get_speed() + change;
+        return self.set_speed(speed);
+    }
+
+    pub fn get_speed(&self) -> f64 {
+        return self.speed;
+    }
+
+    // whether songs keep their pitch when they're sped up or slowed down
+    pub fn set_pitch_correction(&mut self, pitch_correction: bool) -> Result<(),&str> {
+        self.pitch_correction = pitch_correction;
+        return self.send(PlayerCommand::SetSpeed(self.speed, self.pitch_correction));
+    }
+
+    pub fn toggle_pitch_correction(&mut self) -> Result<(),&str> {
+        let pitch_correction = !self.has_pitch_correction();
+        return self.set_pitch_correction(pitch_correction);
+    }
+
+    pub fn has_pitch_correction(&self) -> bool {
+        return self.pitch_correction;
+    }
+
+    // only sent when they change
+    pub fn set_effects(&mut self, effects: &Effects) -> Result<(),&str> {
+        if self.effects == *effects {
+            return Ok(());
+        }
+        self.effects = effects.clone();
+        return self.send(PlayerCommand::SetEffects(effects.clone()));
+    }
+
+    // Keep playing the part between start and end of the current song over and over. None
+    // plays on normally, so does starting another song.
+    pub fn set_loop(&mut self, ab_loop: Option<(f64, f64)>) -> Result<(),&str> {
+        self.ab_loop = ab_loop;
+        return self.send(PlayerCommand::SetLoop(ab_loop));
+    }
+
+    pub fn get_loop(&self) -> Option<(f64, f64)> {
+        return self.ab_loop;
+    }
+
+    // ask for the output devices, a later poll() gives them as PlayerEvent::Devices
+    pub fn refresh_devices(&mut self) -> Result<(),&str> {
+        return self.send(PlayerCommand::ListDevices);
+    }
+
+    // switches the output right away, the song keeps playing
+    pub fn set_device(&mut self, name: &str) -> Result<(),&str> {
+        self.device = String::from(name);
+        return self.send(PlayerCommand::SetDevice(String::from(name)));
+    }
+
+    pub fn get_device(&self) -> &str {
+        return &self.device;
+    }
+
+    // seek relative to the current position, negative goes back
+    pub fn seek(&mut self, seconds: f64) -> Result<(),&str> {
+        return self.send(PlayerCommand::Seek(seconds));
+    }
+
+    pub fn seek_to(&mut self, seconds: f64) -> Result<(),&str> {
+        return self.send(PlayerCommand::SeekTo(seconds));
+    }
+
+    pub fn stop(&mut self) -> Result<(),&str> {
+        self.reset();
+        return self.send(PlayerCommand::Stop);
+    }
+
+    pub fn get_time_pos(&self) -> f64 {
+        return self.time_pos;
+    }
+
+    // in seconds of the song, not of playback
+    pub fn get_time_left(&self) -> f64 {
+        return (self.duration - self.time_pos).max(0.0);
+    }
+
+    pub fn get_song_duration(&self) -> f64 {
+        return self.duration;
+    }
+
+    // Keep the song that plays after the current one loaded in mpv's playlist so it starts
+    // without a gap. Replaces whatever was preloaded before, None just clears it.
+    pub fn preload(&mut self, path: Option<&str>) {
+        if self.preloaded.as_deref() == path {
+            return;
+        }
+        self.preloaded = path.map(String::from);
+        self.send(PlayerCommand::Preload(self.preloaded.clone())).ok();
+    }
+}
+
+// a song being faded out while the next one fades in
+struct Fade {
+    backend: Box<dyn PlayerBackend>,
+    start: Instant,
+    length: f64, // in seconds
+}
+
+// Runs on the player thread and owns the backends
+struct Engine {
+    make_backend: MakeBackend,
+    backend: Box<dyn PlayerBackend>,
+    events: Sender<PlayerEvent>,
+    preloaded: Option<String>, // path of the song appended to the backend after the current one
+    current: Option<String>, // path of the song that's playing
+    loading: Option<String>, // path of the song the backend is starting on
+    gains: HashMap<String,f64>, // replaygain per path, in dB
+    volume: f64, // what the user set, the actual volume changes during crossfades
+    muted: bool,
+    speed: f64,
+    pitch_correction: bool,
+    device: Option<String>, // None leaves the backend's default
+    effects: Effects,
+    ab_loop: Option<(f64, f64)>,
+    fade: Option<Fade>,
+    spare: Option<Box<dyn PlayerBackend>>, // kept from the last crossfade so it can be reused
+    start_at: Option<f64>, // seek here once the song is loaded
+    last_position: Instant,
+}
+
+impl Engine {
+    fn new(make_backend: MakeBackend, events: Sender<PlayerEvent>) -> Engine {
+        return Engine {
+            backend: make_backend(),
+            make_backend,
+            events,
+            preloaded: None,
+            current: None,
+            loading: None,
+            gains: HashMap::new(),
+            volume: 100.0,
+            muted: false,
+            speed: 1.0,
+            pitch_correction: true,
+            device: None,
+            effects: Effects::flat(),
+            ab_loop: None,
+            fade: None,
+            spare: None,
+            start_at: None,
+            last_position: Instant::now(),
+        };
+    }
+
+    // runs until the app's side of the player is dropped
+    fn run(&mut self, commands: Receiver<PlayerCommand>) {
+        loop {
+            loop {
+                match commands.try_recv() {
+                    Ok(c) => self.handle(c),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+
+            let mut timeout = EVENT_WAIT_SECS;
+            while let Some(event) = self.backend.wait_event(timeout) {
+                self.handle_backend_event(event);
+                timeout = 0.0;
+            }
+
+            self.update_fade();
+            self.check_loop();
+            if self.last_position.elapsed() >= POSITION_INTERVAL {
+                self.send_position();
+                self.last_position = Instant::now();
+            }
+        }
+    }
+
+    fn send(&self, event: PlayerEvent) {
+        // the app is gone if this fails, run() stops on its own then
+        self.events.send(event).ok();
+    }
+
+    // pass failures on to the app
+    fn check(&self, result: Result<(),String>) {
+        match result {
+            Ok(_) => {},
+            Err(e) => self.send(PlayerEvent::Error(e)),
+        }
+    }
+
+    fn handle(&mut self, command: PlayerCommand) {
+        match command {
+            PlayerCommand::Play(path, start) => {
+                // pause sticks around between files
+                self.backend.set_pause(false).ok();
+                self.send(PlayerEvent::Paused(false));
+                self.finish_fade();
+                self.preloaded = None;
+                self.start_at = start;
+                let result = self.backend.load(&path);
+                self.check(result);
+                self.loading = Some(path);
+            },
+            PlayerCommand::Crossfade(path, seconds) => self.crossfade(&path, seconds),
+            PlayerCommand::Preload(path) => self.preload(path),
+            PlayerCommand::Stop => {
+                self.finish_fade();
+                self.preloaded = None;
+                let result = self.backend.stop();
+                self.check(result);
+            },
+            PlayerCommand::Pause => {
+                self.finish_fade();
+                self.backend.set_pause(true).ok();
+                self.send(PlayerEvent::Paused(true));
+            },
+            PlayerCommand::Resume => {
+                self.backend.set_pause(false).ok();
+                self.send(PlayerEvent::Paused(false));
+            },
+            PlayerCommand::Seek(seconds) => {
+                let result = self.backend.seek(seconds);
+                self.check(result);
+            },
+            PlayerCommand::SeekTo(seconds) => {
+                let result = self.backend.seek_to(seconds);
+                self.check(result);
+            },
+            PlayerCommand::SetVolume(volume) => {
+                self.volume = volume;
+                // update_fade() fades towards the new volume during a crossfade
+                if self.fade.is_none() {
+                    self.backend.set_volume(volume).ok();
+                }
+            },
+            PlayerCommand::SetMute(mute) => {
+                self.muted = mute;
+                match &mut self.fade {
+                    Some(f) => { f.backend.set_mute(mute).ok(); },
+                    None => {},
+                }
+                self.backend.set_mute(mute).ok();
+            },
+            PlayerCommand::SetGain(path, gain) => {
+                if self.current.as_ref() == Some(&path) {
+                    let result = self.backend.set_gain(gain);
+                    self.check(result);
+                }
+                self.gains.insert(path, gain);
+            },
+            PlayerCommand::SetSpeed(speed, pitch_correction) => {
+                match self.backend.set_speed(speed, pitch_correction) {
+                    Ok(_) => {
+                        self.speed = speed;
+                        self.pitch_correction = pitch_correction;
+                        match &mut self.fade {
+                            Some(f) => { f.backend.set_speed(speed, pitch_correction).ok(); },
+                            None => {},
+                        }
+                    },
+                    Err(e) => {
+                        // the backend may have taken half of it, go back to what was playing
+                        self.backend.set_speed(self.speed, self.pitch_correction).ok();
+                        self.send(PlayerEvent::Error(e));
+                        self.send(PlayerEvent::SpeedRejected { speed: self.speed, pitch_correction: self.pitch_correction });
+                    },
+                }
+            },
+            PlayerCommand::ListDevices => {
+                self.send(PlayerEvent::Devices(self.backend.get_devices()));
+            },
+            PlayerCommand::SetDevice(name) => {
+                match &mut self.fade {
+                    Some(f) => { f.backend.set_device(&name).ok(); },
+                    None => {},
+                }
+                let result = self.backend.set_device(&name);
+                self.check(result);
+                self.device = Some(name);
+            },
+            PlayerCommand::SetEffects(effects) => {
+                // the outgoing song of a crossfade keeps what it had
+                let result = self.backend.set_effects(&effects);
+                self.check(result);
+                self.effects = effects;
+            },
+            PlayerCommand::SetLoop(ab_loop) => { self.ab_loop = ab_loop; },
+        }
+    }
+
+    fn handle_backend_event(&mut self, event: BackendEvent) {
+        match event {
+            BackendEvent::Loaded(path) => {
+                match self.start_at.take() {
+                    Some(s) => {
+                        let result = self.backend.seek_to(s);
+                        self.check(result);
+                    },
+                    None => {},
+                }
+                let gain = self.gains.get(&path).copied().unwrap_or(0.0);
+                let result = self.backend.set_gain(gain);
+                self.check(result);
+                self.current = Some(path.clone());
+                self.loading = None;
+                self.ab_loop = None;
+                self.send(PlayerEvent::Started(path));
+            },
+            BackendEvent::Ended { reason, error, bad_file } => {
+                match error {
+                    Some(e) => self.send(PlayerEvent::Error(e)),
+                    None => {},
+                }
+                self.current = None;
+                // a song that fails usually never got loaded in the first place. only the file
+                // being loaded can be to blame, and only if the failure was with the file itself
+                let mut reason = reason;
+                if reason == FinishReason::Error {
+                    match self.loading.take() {
+                        Some(p) if bad_file => self.send(PlayerEvent::Unplayable(p)),
+                        _ => {
+                            // nobody to blame, go on as if the song had ended. not every backend
+                            // moves on to the preloaded song after an error, so the app picks
+                            // what comes next
+                            self.backend.clear_appended().ok();
+                            self.preloaded = None;
+                            reason = FinishReason::Eof;
+                        },
+                    }
+                }
+                // the backend moves on to the preloaded song unless it was stopped. a stop leaves
+                // loading alone, it's what replacing a song with another one looks like too
+                let next = match reason {
+                    FinishReason::Stopped => None,
+                    _ => self.preloaded.take(),
+                };
+                match &next {
+                    Some(p) => { self.loading = Some(p.clone()); },
+                    None => {},
+                }
+                self.send(PlayerEvent::Finished { reason, next });
+            },
+            BackendEvent::Idle => self.send(PlayerEvent::Idle),
+        }
+    }
+
+    // go back to the start of the loop once its end is reached
+    fn check_loop(&mut self) {
+        let (start, end) = match self.ab_loop {
+            Some(l) => l,
+            None => { return; },
+        };
+        match self.backend.get_time_pos() {
+            Some(t) if t >= end => {
+                let result = self.backend.seek_to(start);
+                self.check(result);
+            },
+            _ => {},
+        }
+    }
+
+    fn send_position(&self) {
+        match (self.backend.get_time_pos(), self.backend.get_duration()) {
+            (Some(time), Some(duration)) => self.send(PlayerEvent::Position { time, duration }),
+            _ => {},
+        }
+    }
+
+    fn preload(&mut self, path: Option<String>) {
+        if self.preloaded == path {
+            return;
+        }
+        let result = self.backend.clear_appended();
+        self.check(result);
+        self.preloaded = None;
+        match path {
+            Some(p) => {
+                match self.backend.append(&p) {
+                    Ok(_) => { self.preloaded = Some(p); },
+                    Err(e) => self.send(PlayerEvent::Error(e)),
+                }
+            },
+            None => {},
+        }
+    }
+
+    fn crossfade(&mut self, path: &str, seconds: f64) {
+        // a second backend would write the same wav file, the song starts right away instead
+        if self.device.as_ref().map(|d| d.starts_with("wav:")) == Some(true) {
+            self.handle(PlayerCommand::Play(String::from(path), None));
+            return;
+        }
+        // the outgoing song shouldn't move on to what was preloaded
+        self.preload(None);
+        self.finish_fade();
+
+        let mut incoming = match self.spare.take() {
+            Some(b) => b,
+            None => (self.make_backend)(),
+        };
+        incoming.set_volume(0.0).ok();
+        incoming.set_mute(self.muted).ok();
+        incoming.set_speed(self.speed, self.pitch_correction).ok();
+        match &self.device {
+            Some(d) => { incoming.set_device(d).ok(); },
+            None => {},
+        }
+        incoming.set_effects(&self.effects).ok();
+        incoming.set_pause(false).ok();
+        let outgoing = mem::replace(&mut self.backend, incoming);
+        self.fade = Some(Fade {
+            backend: outgoing,
+            start: Instant::now(),
+            length: seconds,
+        });
+
+        let result = self.backend.load(path);
+        self.check(result);
+        self.loading = Some(String::from(path));
+    }
+
+    // moves a crossfade along
+    fn update_fade(&mut self) {
+        let progress = match &mut self.fade {
+            Some(f) => {
+                // nobody listens to the outgoing song's events
+                while f.backend.wait_event(0.0).is_some() {}
+                (f.start.elapsed().as_secs_f64() / f.length).min(1.0)
+            },
+            None => { return; },
+        };
+        if progress >= 1.0 {
+            self.finish_fade();
+            return;
+        }
+        let volume = self.volume;
+        match &mut self.fade {
+            Some(f) => { f.backend.set_volume(volume * (1.0 - progress)).ok(); },
+            None => {},
+        }
+        self.backend.set_volume(volume * progress).ok();
+    }
+
+    // stop the outgoing song of a crossfade right away
+    fn finish_fade(&mut self) {
+        match self.fade.take() {
+            Some(mut f) => {
+                f.backend.stop().ok();
+                // drop what stopping it reported
+                while f.backend.wait_event(0.0).is_some() {}
+                self.spare = Some(f.backend);
+                let volume = self.volume;
+                self.backend.set_volume(volume).ok();
+            },
+            None => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::nullbackend::Clock;
+
+    // an engine on the null backend where a, b and c are three minute songs. nothing else exists
+    fn engine(clock: &Clock) -> (Engine, Receiver<PlayerEvent>) {
+        let lengths : HashMap<String,f64> = ["a", "b", "c"].iter().map(|p| (String::from(*p), 180.0)).collect();
+        let clock = clock.clone();
+        let make_backend : MakeBackend = Box::new(move || {
+            return Box::new(nullbackend::new(NULL_SONG_LENGTH, lengths.clone(), clock.clone()));
+        });
+        let (sender, receiver) = mpsc::channel();
+        return (Engine::new(make_backend, sender), receiver);
+    }
+
+    // what run() does with the backend's events, without waiting
+    fn pump(engine: &mut Engine) {
+        while let Some(event) = engine.backend.wait_event(0.0) {
+            engine.handle_backend_event(event);
+        }
+    }
+
+    // the events that say what played, in order
+    fn played(events: &Receiver<PlayerEvent>) -> Vec<String> {
+        return events.try_iter().filter_map(|e| match e {
+            PlayerEvent::Started(p) => Some(format!("started {}", p)),
+            PlayerEvent::Finished { reason, next } => {
+                let reason = match reason {
+                    FinishReason::Eof => "eof",
+                    FinishReason::Stopped => "stopped",
+                    FinishReason::Error => "error",
+                };
+                Some(format!("finished {} {}", reason, next.unwrap_or_default()).trim_end().to_string())
+            },
+            PlayerEvent::Unplayable(p) => Some(format!("unplayable {}", p)),
+            PlayerEvent::Idle => Some(String::from("idle")),
+            _ => None,
+        }).collect();
+    }
+
+    #[test]
+    fn moves_on_to_preloaded_song() {
+        let clock = Clock::manual();
+        let (mut engine, events) = engine(&clock);
+        engine.handle(PlayerCommand::Play(String::from("a"), None));
+        engine.handle(PlayerCommand::Preload(Some(String::from("b"))));
+        pump(&mut engine);
+        clock.step(180.0);
+        pump(&mut engine);
+        clock.step(180.0);
+        pump(&mut engine);
+        assert_eq!(played(&events), vec!["started a", "finished eof b", "started b", "finished eof", "idle"]);
+    }
+
+    #[test]
+    fn replaced_song_isnt_blamed_for_the_next() {
+        let clock = Clock::manual();
+        let (mut engine, events) = engine(&clock);
+        engine.handle(PlayerCommand::Play(String::from("a"), None));
+        pump(&mut engine);
+        engine.handle(PlayerCommand::Play(String::from("missing"), None));
+        pump(&mut engine);
+        assert_eq!(played(&events), vec!["started a", "finished stopped", "unplayable missing", "finished error"]);
+    }
+
+    #[test]
+    fn stopped_song_isnt_blamed_for_the_next() {
+        let clock = Clock::manual();
+        let (mut engine, events) = engine(&clock);
+        engine.handle(PlayerCommand::Play(String::from("a"), None));
+        pump(&mut engine);
+        engine.handle(PlayerCommand::Stop);
+        pump(&mut engine);
+        engine.handle(PlayerCommand::Play(String::from("missing"), None));
+        pump(&mut engine);
+        assert_eq!(played(&events), vec!["started a", "finished stopped", "idle", "unplayable missing", "finished error"]);
+    }
+
+    #[test]
+    fn missing_preloaded_song_is_blamed() {
+        let clock = Clock::manual();
+        let (mut engine, events) = engine(&clock);
+        engine.handle(PlayerCommand::Play(String::from("a"), None));
+        engine.handle(PlayerCommand::Preload(Some(String::from("missing"))));
+        pump(&mut engine);
+        clock.step(180.0);
+        pump(&mut engine);
+        assert_eq!(played(&events), vec!["started a", "finished eof missing", "unplayable missing", "finished error"]);
+    }
+
+    #[test]
+    fn failing_song_without_blame_moves_on() {
+        let clock = Clock::manual();
+        let (mut engine, events) = engine(&clock);
+        engine.handle(PlayerCommand::Play(String::from("a"), None));
+        engine.handle(PlayerCommand::Preload(Some(String::from("b"))));
+        pump(&mut engine);
+        // like the audio output going away halfway through
+        clock.step(60.0);
+        engine.handle_backend_event(BackendEvent::Ended {
+            reason: FinishReason::Error,
+            error: Some(String::from("audio output lost")),
+            bad_file: false,
+        });
+        assert_eq!(played(&events), vec!["started a", "finished eof"]);
+        assert_eq!(engine.preloaded, None);
+    }
+
+    #[test]
+    fn pausing_stops_the_clock() {
+        let clock = Clock::manual();
+        let (mut engine, events) = engine(&clock);
+        engine.handle(PlayerCommand::Play(String::from("a"), None));
+        pump(&mut engine);
+        engine.handle(PlayerCommand::Pause);
+        clock.step(120.0);
+        pump(&mut engine);
+        engine.handle(PlayerCommand::Resume);
+        clock.step(120.0);
+        pump(&mut engine);
+        assert_eq!(engine.backend.get_time_pos(), Some(120.0));
+        assert_eq!(played(&events), vec!["started a"]);
+    }
+}
@@ -0,0 +1,215 @@
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+
+use mpv::{MpvHandler, MpvHandlerBuilder, MpvFormat, EndFileReason};
+
+use crate::player::FinishReason;
+use crate::player::effects::{Effects, EQ_BANDS};
+use crate::player::backend::{PlayerBackend, BackendEvent, AudioDevice};
+
+// the mpv crate frees strings before handing them out, so they're read through libmpv directly
+extern "C" {
+    fn mpv_get_property_string(ctx: *mut c_void, name: *const c_char) -> *mut c_char;
+    fn mpv_free(data: *mut c_void);
+}
+
+// plays through libmpv
+pub struct MpvBackend {
+    handler: MpvHandler,
+    gain: f64,
+    effects: Effects,
+}
+
+pub fn new() -> MpvBackend {
+    let mut handler = MpvHandlerBuilder::new().expect("something");
+    handler.set_option("vid","no").expect("");
+    handler.set_option("prefetch-playlist","yes").expect("");
+    let backend = MpvBackend {
+        handler: handler.build().expect(""),
+        gain: 0.0,
+        effects: Effects::flat(),
+    };
+    return backend;
+}
+
+impl MpvBackend {
+    fn command(&mut self, command : &[&str]) -> Result<(),String> {
+        match self.handler.command(command) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{}: {}", command[0], e)),
+        }
+    }
+
+    // gain and effects share mpv's filter chain, so it's rebuilt whenever one of them changes
+    fn update_filters(&mut self) -> Result<(),String> {
+        let mut filters : Vec<String> = Vec::new();
+        if self.gain != 0.0 {
+            filters.push(format!("volume={}dB", self.gain));
+        }
+        for (band, gain) in EQ_BANDS.iter().zip(self.effects.eq.iter()) {
+            if *gain != 0.0 {
+                filters.push(format!("equalizer=f={}:width_type=o:width=1:g={}", band, gain));
+            }
+        }
+        // balance only ever turns one side down
+        let left = (1.0 - self.effects.balance).min(1.0);
+        let right = (1.0 + self.effects.balance).min(1.0);
+        if self.effects.mono {
+            filters.push(format!("pan=stereo|c0={l}*c0+{l}*c1|c1={r}*c0+{r}*c1", l = left / 2.0, r = right / 2.0));
+        }
+        else if self.effects.balance != 0.0 {
+            filters.push(format!("pan=stereo|c0={}*c0|c1={}*c1", left, right));
+        }
+        if self.effects.compressor {
+            filters.push(String::from("acompressor=threshold=0.125:ratio=4:attack=20:release=250:makeup=2"));
+        }
+
+        if filters.len() == 0 {
+            return self.set_property("af", "");
+        }
+        let chain = format!("lavfi=[{}]", filters.join(","));
+        return self.set_property("af", &chain[..]);
+    }
+
+    // copies the property out before mpv frees it. paths don't have to be utf-8
+    fn get_string(&self, property: &str) -> Option<String> {
+        let name = CString::new(property).ok()?;
+        unsafe {
+            let value = mpv_get_property_string(self.handler.raw() as *mut c_void, name.as_ptr());
+            if value.is_null() {
+                return None;
+            }
+            let owned = CStr::from_ptr(value).to_string_lossy().to_string();
+            mpv_free(value as *mut c_void);
+            return Some(owned);
+        }
+    }
+
+    fn set_property<T : MpvFormat>(&mut self, property: &str, value: T) -> Result<(),String> {
+        match self.handler.set_property(property, value) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{}: {}", property, e)),
+        }
+    }
+}
+
+impl PlayerBackend for MpvBackend {
+    fn load(&mut self, path: &str) -> Result<(),String> {
+        return self.command(&["loadfile", path, "replace"]);
+    }
+
+    fn append(&mut self, path: &str) -> Result<(),String> {
+        return self.command(&["loadfile", path, "append"]);
+    }
+
+    fn clear_appended(&mut self) -> Result<(),String> {
+        return self.command(&["playlist-clear"]); // everything but the current song
+    }
+
+    fn stop(&mut self) -> Result<(),String> {
+        return self.command(&["stop"]);
+    }
+
+    fn set_pause(&mut self, pause: bool) -> Result<(),String> {
+        return self.set_property("pause", pause);
+    }
+
+    fn seek(&mut self, seconds: f64) -> Result<(),String> {
+        let offset = seconds.to_string();
+        return self.command(&["seek", &offset[..], "relative"]);
+    }
+
+    fn seek_to(&mut self, seconds: f64) -> Result<(),String> {
+        let position = seconds.to_string();
+        return self.command(&["seek", &position[..], "absolute"]);
+    }
+
+    fn set_volume(&mut self, volume: f64) -> Result<(),String> {
+        return self.set_property("volume", volume);
+    }
+
+    fn set_mute(&mut self, mute: bool) -> Result<(),String> {
+        return self.set_property("mute", mute);
+    }
+
+    fn set_gain(&mut self, gain: f64) -> Result<(),String> {
+        self.gain = gain;
+        return self.update_filters();
+    }
+
+    fn set_effects(&mut self, effects: &Effects) -> Result<(),String> {
+        self.effects = effects.clone();
+        return self.update_filters();
+    }
+
+    fn set_speed(&mut self, speed: f64, pitch_correction: bool) -> Result<(),String> {
+        self.set_property("audio-pitch-correction", pitch_correction)?;
+        return self.set_property("speed", speed);
+    }
+
+    fn get_devices(&self) -> Vec<AudioDevice> {
+        // node properties come out as json when read as a string
+        let list = match self.get_string("audio-device-list") {
+            Some(l) => l,
+            None => { return Vec::new(); },
+        };
+        let parsed = match json::parse(&list) {
+            Ok(p) => p,
+            Err(_) => { return Vec::new(); },
+        };
+        let mut devices : Vec<AudioDevice> = Vec::new();
+        for device in parsed.members() {
+            match (device["name"].as_str(), device["description"].as_str()) {
+                (Some(name), description) => devices.push(AudioDevice {
+                    name: String::from(name),
+                    description: String::from(description.unwrap_or(name)),
+                }),
+                _ => {},
+            }
+        }
+        return devices;
+    }
+
+    fn set_device(&mut self, name: &str) -> Result<(),String> {
+        return self.set_property("audio-device", name);
+    }
+
+    fn get_time_pos(&self) -> Option<f64> {
+        return self.handler.get_property("time-pos").ok();
+    }
+
+    fn get_duration(&self) -> Option<f64> {
+        return self.handler.get_property("duration").ok();
+    }
+
+    fn wait_event(&mut self, timeout: f64) -> Option<BackendEvent> {
+        let mut timeout = timeout;
+        loop {
+            let event = match self.handler.wait_event(timeout)? {
+                mpv::Event::FileLoaded => {
+                    Some(BackendEvent::Loaded(self.get_string("path").unwrap_or_default()))
+                },
+                mpv::Event::EndFile(result) => {
+                    Some(match result {
+                        Ok(EndFileReason::MPV_END_FILE_REASON_EOF) => BackendEvent::Ended { reason: FinishReason::Eof, error: None, bad_file: false },
+                        Ok(_) => BackendEvent::Ended { reason: FinishReason::Stopped, error: None, bad_file: false },
+                        Err(e) => {
+                            // audio output failures and such aren't the song's fault
+                            let bad_file = matches!(e, mpv::Error::MPV_ERROR_LOADING_FAILED
+                                | mpv::Error::MPV_ERROR_UNKNOWN_FORMAT
+                                | mpv::Error::MPV_ERROR_NOTHING_TO_PLAY);
+                            BackendEvent::Ended { reason: FinishReason::Error, error: Some(e.to_string()), bad_file }
+                        },
+                    })
+                },
+                mpv::Event::Idle => Some(BackendEvent::Idle),
+                _ => None,
+            };
+            match event {
+                Some(e) => { return Some(e); },
+                // not something we care about, see if anything else is waiting
+                None => { timeout = 0.0; },
+            }
+        }
+    }
+}
@@ -0,0 +1,501 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::Time;
+
+use crate::player::FinishReason;
+use crate::player::effects::{Effects, EQ_BANDS};
+use crate::player::backend::{PlayerBackend, BackendEvent, AudioDevice};
+use crate::player::sink::{self, Sink};
+
+// how long to wait for the sink to take more before trying again
+const SINK_POLL : Duration = Duration::from_millis(5);
+
+// compressor settings, the same as the mpv backend's acompressor
+const COMPRESSOR_THRESHOLD : f32 = 0.125;
+const COMPRESSOR_RATIO : f32 = 4.0;
+const COMPRESSOR_ATTACK_SECS : f32 = 0.02;
+const COMPRESSOR_RELEASE_SECS : f32 = 0.25;
+const COMPRESSOR_MAKEUP : f32 = 2.0;
+
+// A song being decoded
+struct Stream {
+    path: String,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    rate: u32,
+    channels: u32,
+    duration: Option<f64>,
+    frames: u64, // decoded so far, for the position
+}
+
+fn open_stream(path: &str) -> Result<Stream, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some(e) => { hint.with_extension(e); },
+        None => {},
+    }
+    let probed = symphonia::default::get_probe()
+        .format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|e| format!("{}: {}", path, e))?;
+    let format = probed.format;
+    let track = format.default_track().ok_or(format!("{}: no audio", path))?;
+    let params = track.codec_params.clone();
+    let decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .map_err(|e| format!("{}: {}", path, e))?;
+    let rate = params.sample_rate.ok_or(format!("{}: unknown sample rate", path))?;
+    let channels = params.channels.map(|c| c.count() as u32).unwrap_or(2);
+    return Ok(Stream {
+        path: String::from(path),
+        track_id: track.id,
+        format,
+        decoder,
+        rate,
+        channels,
+        duration: params.n_frames.map(|n| n as f64 / rate as f64),
+        frames: 0,
+    });
+}
+
+impl Stream {
+    // the next bit of audio, interleaved. None at the end of the song
+    fn decode(&mut self) -> Result<Option<Vec<f32>>, String> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(p) => p,
+                Err(Error::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => { return Ok(None); },
+                Err(e) => { return Err(format!("{}: {}", self.path, e)); },
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(d) => d,
+                // a damaged packet, skip it
+                Err(Error::DecodeError(_)) => continue,
+                Err(e) => { return Err(format!("{}: {}", self.path, e)); },
+            };
+            let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            samples.copy_interleaved_ref(decoded);
+            self.frames += (samples.samples().len() / self.channels as usize) as u64;
+            return Ok(Some(samples.samples().to_vec()));
+        }
+    }
+
+    fn seek(&mut self, seconds: f64) -> Result<(), String> {
+        let to = SeekTo::Time { time: Time::from(seconds.max(0.0)), track_id: Some(self.track_id) };
+        let seeked = self.format.seek(SeekMode::Coarse, to).map_err(|e| format!("seek: {}", e))?;
+        self.decoder.reset();
+        self.frames = seeked.actual_ts;
+        return Ok(());
+    }
+
+    fn position(&self) -> f64 {
+        return self.frames as f64 / self.rate as f64;
+    }
+}
+
+// one peaking filter of the equalizer, for one channel
+#[derive(Clone, Copy)]
+struct Biquad {
+    b0: f64, b1: f64, b2: f64, a1: f64, a2: f64,
+    x1: f64, x2: f64, y1: f64, y2: f64,
+}
+
+impl Biquad {
+    // an octave wide peak at frequency, see the audio eq cookbook
+    fn peaking(frequency: f64, gain: f64, rate: f64) -> Biquad {
+        let a = 10f64.powf(gain / 40.0);
+        let w0 = 2.0 * PI * frequency / rate;
+        let alpha = w0.sin() / (2.0 * 2f64.sqrt());
+        let a0 = 1.0 + alpha / a;
+        return Biquad {
+            b0: (1.0 + alpha * a) / a0,
+            b1: (-2.0 * w0.cos()) / a0,
+            b2: (1.0 - alpha * a) / a0,
+            a1: (-2.0 * w0.cos()) / a0,
+            a2: (1.0 - alpha / a) / a0,
+            x1: 0.0, x2: 0.0, y1: 0.0, y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2 - self.a1 * self.y1 - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        return y;
+    }
+}
+
+// Decodes with symphonia and plays through alsa, no mpv needed. Everything happens on the player
+// thread: waiting for events keeps the sink topped up.
+pub struct NativeBackend {
+    stream: Option<Stream>,
+    appended: VecDeque<String>,
+    sink: Option<Box<dyn Sink>>,
+    sink_format: (u32, u32), // rate and channels the sink was opened with
+    device: String,
+    pending: Vec<f32>, // decoded but not taken by the sink yet
+    paused: bool,
+    volume: f64,
+    muted: bool,
+    gain: f64,
+    speed: f64,
+    effects: Effects,
+    eq: Vec<Vec<Biquad>>, // per channel, per band
+    envelope: f32, // of the compressor
+    events: VecDeque<BackendEvent>,
+}
+
+pub fn new() -> NativeBackend {
+    return NativeBackend {
+        stream: None,
+        appended: VecDeque::new(),
+        sink: None,
+        sink_format: (0, 0),
+        device: String::from("default"),
+        pending: Vec::new(),
+        paused: false,
+        volume: 100.0,
+        muted: false,
+        gain: 0.0,
+        speed: 1.0,
+        effects: Effects::flat(),
+        eq: Vec::new(),
+        envelope: 0.0,
+        events: VecDeque::new(),
+    };
+}
+
+impl NativeBackend {
+    fn start(&mut self, path: &str) {
+        self.pending.clear();
+        match open_stream(path) {
+            Ok(s) => {
+                self.stream = Some(s);
+                self.update_eq();
+                self.events.push_back(BackendEvent::Loaded(String::from(path)));
+            },
+            Err(e) => {
+                self.stream = None;
+                self.events.push_back(BackendEvent::Ended { reason: FinishReason::Error, error: Some(e), bad_file: true });
+            },
+        }
+    }
+
+    fn end(&mut self, reason: FinishReason, error: Option<String>, bad_file: bool) {
+        if self.stream.take().is_some() {
+            self.events.push_back(BackendEvent::Ended { reason, error, bad_file });
+        }
+        self.pending.clear();
+    }
+
+    // the song ran out, go on with what was appended. the sink stays open if it fits, which is
+    // what makes it gapless
+    fn next(&mut self) {
+        self.end(FinishReason::Eof, None, false);
+        match self.appended.pop_front() {
+            Some(p) => self.start(&p),
+            None => self.events.push_back(BackendEvent::Idle),
+        }
+    }
+
+    fn update_eq(&mut self) {
+        let (rate, channels) = match &self.stream {
+            Some(s) => (s.rate as f64, s.channels as usize),
+            None => { return; },
+        };
+        self.eq = vec![self.eq_bands(rate); channels];
+    }
+
+    // the filters of one channel
+    fn eq_bands(&self, rate: f64) -> Vec<Biquad> {
+        return EQ_BANDS.iter().zip(self.effects.eq.iter())
+            .filter(|(f, g)| **g != 0.0 && **f < rate / 2.0)
+            .map(|(f, g)| Biquad::peaking(*f, *g, rate))
+            .collect();
+    }
+
+    // volume, gain and effects, in place
+    fn process(&mut self, samples: &mut [f32], channels: usize, rate: u32) {
+        let level = if self.muted { 0.0 } else { (self.volume / 100.0) * 10f64.powf(self.gain / 20.0) } as f32;
+        let left = (1.0 - self.effects.balance).min(1.0) as f32;
+        let right = (1.0 + self.effects.balance).min(1.0) as f32;
+        let attack = (-1.0 / (COMPRESSOR_ATTACK_SECS * rate as f32)).exp();
+        let release = (-1.0 / (COMPRESSOR_RELEASE_SECS * rate as f32)).exp();
+        // every channel needs its own filters, even ones the eq wasn't set up for
+        if self.eq.len() < channels {
+            let bands = self.eq_bands(rate as f64);
+            self.eq.resize(channels, bands);
+        }
+        for frame in samples.chunks_mut(channels) {
+            for (c, sample) in frame.iter_mut().enumerate() {
+                let mut s = *sample as f64;
+                for band in self.eq[c].iter_mut() {
+                    s = band.process(s);
+                }
+                *sample = s as f32 * level;
+            }
+            if channels == 2 {
+                if self.effects.mono {
+                    let mixed = (frame[0] + frame[1]) / 2.0;
+                    frame[0] = mixed;
+                    frame[1] = mixed;
+                }
+                frame[0] *= left;
+                frame[1] *= right;
+            }
+            if self.effects.compressor {
+                let peak = frame.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+                let coefficient = if peak > self.envelope { attack } else { release };
+                self.envelope = coefficient * self.envelope + (1.0 - coefficient) * peak;
+                let mut gain = COMPRESSOR_MAKEUP;
+                if self.envelope > COMPRESSOR_THRESHOLD {
+                    let over = self.envelope / COMPRESSOR_THRESHOLD;
+                    gain *= over.powf(1.0 / COMPRESSOR_RATIO - 1.0);
+                }
+                for sample in frame.iter_mut() {
+                    *sample *= gain;
+                }
+            }
+        }
+    }
+
+    // give the sink as much as it takes, decoding more when needed. returns whether anything
+    // was written
+    fn pump(&mut self) -> bool {
+        if self.paused {
+            return false;
+        }
+        let (rate, channels) = match &self.stream {
+            Some(s) => (s.rate, s.channels),
+            None => { return false; },
+        };
+
+        // speed just plays the samples faster, the pitch goes with it
+        let format = ((rate as f64 * self.speed) as u32, channels);
+        // some sinks can switch over without being reopened
+        let fits = match &mut self.sink {
+            Some(_) if self.sink_format == format => Ok(true),
+            Some(s) => s.set_format(format.0, format.1),
+            None => Ok(false),
+        };
+        match fits {
+            Ok(true) => { self.sink_format = format; },
+            Ok(false) => {
+                // closed first, the old sink may still hold the device
+                self.sink = None;
+                match sink::open(&self.device, format.0, format.1) {
+                    Ok(s) => {
+                        self.sink = Some(s);
+                        self.sink_format = format;
+                    },
+                    Err(e) => {
+                        self.end(FinishReason::Error, Some(e), false);
+                        self.events.push_back(BackendEvent::Idle);
+                        return false;
+                    },
+                }
+            },
+            Err(e) => {
+                // the sink stays open for the songs that do fit
+                self.end(FinishReason::Error, Some(e), false);
+                return false;
+            },
+        }
+
+        let mut wrote = false;
+        loop {
+            if self.pending.len() == 0 {
+                let decoded = match &mut self.stream {
+                    Some(s) => s.decode(),
+                    None => { return wrote; },
+                };
+                match decoded {
+                    Ok(Some(mut samples)) => {
+                        self.process(&mut samples, channels as usize, rate);
+                        self.pending = samples;
+                    },
+                    Ok(None) => {
+                        self.next();
+                        return wrote;
+                    },
+                    Err(e) => {
+                        self.end(FinishReason::Error, Some(e), true);
+                        return wrote;
+                    },
+                }
+            }
+            let written = match &mut self.sink {
+                Some(s) => s.write(&self.pending),
+                None => Ok(0),
+            };
+            match written {
+                Ok(0) => { return wrote; },
+                Ok(frames) => {
+                    self.pending.drain(..frames * channels as usize);
+                    wrote = true;
+                },
+                Err(e) => {
+                    self.sink = None;
+                    self.end(FinishReason::Error, Some(e), false);
+                    return wrote;
+                },
+            }
+        }
+    }
+}
+
+impl PlayerBackend for NativeBackend {
+    fn load(&mut self, path: &str) -> Result<(),String> {
+        self.end(FinishReason::Stopped, None, false);
+        self.appended.clear();
+        match &mut self.sink {
+            Some(s) => s.clear(),
+            None => {},
+        }
+        self.start(path);
+        return Ok(());
+    }
+
+    fn append(&mut self, path: &str) -> Result<(),String> {
+        self.appended.push_back(String::from(path));
+        return Ok(());
+    }
+
+    fn clear_appended(&mut self) -> Result<(),String> {
+        self.appended.clear();
+        return Ok(());
+    }
+
+    fn stop(&mut self) -> Result<(),String> {
+        self.appended.clear();
+        if self.stream.is_some() {
+            self.end(FinishReason::Stopped, None, false);
+            self.events.push_back(BackendEvent::Idle);
+        }
+        match &mut self.sink {
+            Some(s) => s.clear(),
+            None => {},
+        }
+        return Ok(());
+    }
+
+    fn set_pause(&mut self, pause: bool) -> Result<(),String> {
+        self.paused = pause;
+        match &mut self.sink {
+            Some(s) => s.set_pause(pause),
+            None => {},
+        }
+        return Ok(());
+    }
+
+    fn seek(&mut self, seconds: f64) -> Result<(),String> {
+        let position = self.get_time_pos().ok_or(String::from("seek: nothing is playing"))? + seconds;
+        return self.seek_to(position);
+    }
+
+    fn seek_to(&mut self, seconds: f64) -> Result<(),String> {
+        match &mut self.stream {
+            Some(s) => s.seek(seconds)?,
+            None => { return Err(String::from("seek: nothing is playing")); },
+        }
+        self.pending.clear();
+        match &mut self.sink {
+            Some(s) => s.clear(),
+            None => {},
+        }
+        return Ok(());
+    }
+
+    // volume and gain apply to what's decoded from now on, the sink's buffer plays out as it was
+    fn set_volume(&mut self, volume: f64) -> Result<(),String> {
+        self.volume = volume;
+        return Ok(());
+    }
+
+    fn set_mute(&mut self, mute: bool) -> Result<(),String> {
+        self.muted = mute;
+        return Ok(());
+    }
+
+    fn set_gain(&mut self, gain: f64) -> Result<(),String> {
+        self.gain = gain;
+        return Ok(());
+    }
+
+    fn set_effects(&mut self, effects: &Effects) -> Result<(),String> {
+        self.effects = effects.clone();
+        self.update_eq();
+        return Ok(());
+    }
+
+    fn set_speed(&mut self, speed: f64, pitch_correction: bool) -> Result<(),String> {
+        if pitch_correction && speed != 1.0 {
+            return Err(String::from("speed: the native backend can't keep the pitch"));
+        }
+        // the speed is heard as a change of rate, which a wav file can't have halfway through
+        if speed != self.speed && self.device.starts_with("wav:") {
+            return Err(String::from("speed: can't be changed while writing a wav file"));
+        }
+        self.speed = speed;
+        return Ok(());
+    }
+
+    fn get_devices(&self) -> Vec<AudioDevice> {
+        return sink::devices();
+    }
+
+    fn set_device(&mut self, name: &str) -> Result<(),String> {
+        // reopened on the next write
+        self.device = String::from(name);
+        self.sink = None;
+        return Ok(());
+    }
+
+    fn get_time_pos(&self) -> Option<f64> {
+        return self.stream.as_ref().map(|s| s.position());
+    }
+
+    fn get_duration(&self) -> Option<f64> {
+        return self.stream.as_ref().and_then(|s| s.duration);
+    }
+
+    fn wait_event(&mut self, timeout: f64) -> Option<BackendEvent> {
+        let until = Instant::now() + Duration::from_secs_f64(timeout);
+        loop {
+            match self.events.pop_front() {
+                Some(e) => { return Some(e); },
+                None => {},
+            }
+            let wrote = self.pump();
+            if !self.events.is_empty() {
+                continue;
+            }
+            let now = Instant::now();
+            if now >= until {
+                return None;
+            }
+            if !wrote {
+                thread::sleep(SINK_POLL.min(until - now));
+            }
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::{VecDeque, HashMap};
+use std::thread;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::player::FinishReason;
+use crate::player::effects::Effects;
+use crate::player::backend::{PlayerBackend, BackendEvent, AudioDevice};
+
+// Where the null backend's time comes from. The real one moves by however long was waited for
+// events, a manual one only when it's stepped, so tests don't have to sleep through songs.
+// only tests step the clock
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Clone)]
+pub enum Clock {
+    Real,
+    Manual(Arc<Mutex<f64>>), // seconds stepped so far, shared by every backend made with it
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Clock {
+    pub fn manual() -> Clock {
+        return Clock::Manual(Arc::new(Mutex::new(0.0)));
+    }
+
+    // does nothing to the real clock
+    pub fn step(&self, seconds: f64) {
+        match self {
+            Clock::Real => {},
+            Clock::Manual(now) => { *now.lock().unwrap() += seconds; },
+        }
+    }
+
+    fn now(&self) -> f64 {
+        return match self {
+            Clock::Real => 0.0,
+            Clock::Manual(now) => *now.lock().unwrap(),
+        }
+    }
+}
+
+// Plays nothing, but acts like it does. Time only moves forward while waiting for events, so the
+// same calls always end up in the same place.
+pub struct NullBackend {
+    song_length: f64, // songs not in lengths pretend to be this long
+    lengths: HashMap<String,f64>, // songs that are there whatever is on disk, and how long they are
+    clock: Clock,
+    seen: f64, // what the clock said when time was last moved
+    current: Option<String>,
+    appended: VecDeque<String>,
+    time: f64,
+    paused: bool,
+    speed: f64,
+    events: VecDeque<BackendEvent>,
+}
+
+pub fn new(song_length: f64, lengths: HashMap<String,f64>, clock: Clock) -> NullBackend {
+    let backend = NullBackend {
+        song_length,
+        lengths,
+        seen: clock.now(),
+        clock,
+        current: None,
+        appended: VecDeque::new(),
+        time: 0.0,
+        paused: false,
+        speed: 1.0,
+        events: VecDeque::new(),
+    };
+    return backend;
+}
+
+impl NullBackend {
+    fn start(&mut self, path: String) {
+        self.time = 0.0;
+        self.seen = self.clock.now();
+        // missing files fail like they would with real playback
+        if !self.lengths.contains_key(&path) && !Path::new(&path).is_file() {
+            self.events.push_back(BackendEvent::Ended {
+                reason: FinishReason::Error,
+                error: Some(format!("{}: no such file", path)),
+                bad_file: true,
+            });
+            return;
+        }
+        self.events.push_back(BackendEvent::Loaded(path.clone()));
+        self.current = Some(path);
+    }
+
+    fn length(&self) -> f64 {
+        return match &self.current {
+            Some(p) => self.lengths.get(p).copied().unwrap_or(self.song_length),
+            None => 0.0,
+        }
+    }
+
+    // how far the clock moved while waiting up to timeout seconds
+    fn wait(&mut self, timeout: f64) -> f64 {
+        match &self.clock {
+            Clock::Real => {
+                if timeout <= 0.0 {
+                    return 0.0;
+                }
+                // don't let the player thread spin
+                thread::sleep(Duration::from_secs_f64(timeout));
+                return timeout;
+            },
+            Clock::Manual(_) => {
+                let now = self.clock.now();
+                let waited = now - self.seen;
+                self.seen = now;
+                return waited;
+            },
+        }
+    }
+
+    fn end(&mut self, reason: FinishReason) {
+        if self.current.take().is_some() {
+            self.events.push_back(BackendEvent::Ended { reason, error: None, bad_file: false });
+        }
+    }
+}
+
+impl PlayerBackend for NullBackend {
+    fn load(&mut self, path: &str) -> Result<(),String> {
+        self.end(FinishReason::Stopped);
+        self.appended.clear();
+        self.start(String::from(path));
+        return Ok(());
+    }
+
+    fn append(&mut self, path: &str) -> Result<(),String> {
+        self.appended.push_back(String::from(path));
+        return Ok(());
+    }
+
+    fn clear_appended(&mut self) -> Result<(),String> {
+        self.appended.clear();
+        return Ok(());
+    }
+
+    fn stop(&mut self) -> Result<(),String> {
+        self.appended.clear();
+        if self.current.is_some() {
+            self.end(FinishReason::Stopped);
+            self.events.push_back(BackendEvent::Idle);
+        }
+        return Ok(());
+    }
+
+    fn set_pause(&mut self, pause: bool) -> Result<(),String> {
+        self.paused = pause;
+        return Ok(());
+    }
+
+    fn seek(&mut self, seconds: f64) -> Result<(),String> {
+        let position = self.time + seconds;
+        return self.seek_to(position);
+    }
+
+    fn seek_to(&mut self, seconds: f64) -> Result<(),String> {
+        if self.current.is_none() {
+            return Err(String::from("seek: nothing is playing"));
+        }
+        self.time = seconds.clamp(0.0, self.length());
+        return Ok(());
+    }
+
+    fn set_volume(&mut self, _volume: f64) -> Result<(),String> {
+        return Ok(());
+    }
+
+    fn set_mute(&mut self, _mute: bool) -> Result<(),String> {
+        return Ok(());
+    }
+
+    fn set_gain(&mut self, _gain: f64) -> Result<(),String> {
+        return Ok(());
+    }
+
+    fn set_effects(&mut self, _effects: &Effects) -> Result<(),String> {
+        return Ok(());
+    }
+
+    fn set_speed(&mut self, speed: f64, _pitch_correction: bool) -> Result<(),String> {
+        self.speed = speed;
+        return Ok(());
+    }
+
+    fn get_devices(&self) -> Vec<AudioDevice> {
+        return vec![AudioDevice {
+            name: String::from("null"),
+            description: String::from("No output"),
+        }];
+    }
+
+    fn set_device(&mut self, _name: &str) -> Result<(),String> {
+        return Ok(());
+    }
+
+    fn get_time_pos(&self) -> Option<f64> {
+        return self.current.as_ref().map(|_| self.time);
+    }
+
+    fn get_duration(&self) -> Option<f64> {
+        return self.current.as_ref().map(|_| self.length());
+    }
+
+    fn wait_event(&mut self, timeout: f64) -> Option<BackendEvent> {
+        if self.events.is_empty() {
+            let waited = self.wait(timeout);
+            if self.current.is_some() && !self.paused {
+                self.time += waited * self.speed;
+            }
+            if self.current.is_some() && self.time >= self.length() {
+                self.end(FinishReason::Eof);
+                match self.appended.pop_front() {
+                    Some(p) => self.start(p),
+                    None => self.events.push_back(BackendEvent::Idle),
+                }
+            }
+        }
+        return self.events.pop_front();
+    }
+}
@@ -0,0 +1,291 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Write, Seek, SeekFrom, BufWriter, ErrorKind};
+use std::time::Instant;
+
+use alsa::{Direction, ValueOr};
+use alsa::pcm::{PCM, HwParams, Format, Access};
+use alsa::device_name::HintIter;
+
+use crate::player::backend::AudioDevice;
+
+// how much audio a sink holds ahead of what's being heard, in seconds
+const BUFFER_SECS : f64 = 0.2;
+
+// Where the native backend sends its samples. Writing never blocks, a sink takes what fits and
+// says how many frames that was.
+pub trait Sink {
+    // interleaved samples from -1.0 to 1.0
+    fn write(&mut self, samples: &[f32]) -> Result<usize,String>;
+    fn set_pause(&mut self, pause: bool);
+    // throw away what's buffered, after a seek or stop
+    fn clear(&mut self);
+    // take samples of another rate or channel count from now on. Ok(false) if it has to be
+    // reopened for them, an error if it can't take them at all
+    fn set_format(&mut self, rate: u32, channels: u32) -> Result<bool,String>;
+}
+
+// "null" plays nothing, "wav:<path>" writes to a file, anything else is an alsa device
+pub fn open(device: &str, rate: u32, channels: u32) -> Result<Box<dyn Sink>, String> {
+    if device == "null" {
+        return Ok(Box::new(PacedSink::new(rate, channels, None)));
+    }
+    match device.strip_prefix("wav:") {
+        Some(path) => {
+            let wav = WavFile::create(path, rate, channels).map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => format!("{}: already exists, remove it or pick another file", path),
+                _ => format!("{}: {}", path, e),
+            })?;
+            return Ok(Box::new(PacedSink::new(rate, channels, Some(wav))));
+        },
+        None => {},
+    }
+    return Ok(Box::new(AlsaSink::open(device, rate, channels).map_err(|e| format!("{}: {}", device, e))?));
+}
+
+pub fn devices() -> Vec<AudioDevice> {
+    let mut devices : Vec<AudioDevice> = vec![AudioDevice {
+        name: String::from("default"),
+        description: String::from("Default ALSA device"),
+    }];
+    match HintIter::new_str(None, "pcm") {
+        Ok(hints) => {
+            for hint in hints {
+                if hint.direction == Some(Direction::Capture) {
+                    continue;
+                }
+                match hint.name {
+                    Some(name) if name != "default" && name != "null" => devices.push(AudioDevice {
+                        description: hint.desc.map(|d| d.replace('\n', " ")).unwrap_or(name.clone()),
+                        name,
+                    }),
+                    _ => {},
+                }
+            }
+        },
+        Err(_) => {},
+    }
+    devices.push(AudioDevice {
+        name: String::from("wav:rumu.wav"),
+        description: String::from("Write to rumu.wav"),
+    });
+    devices.push(AudioDevice {
+        name: String::from("null"),
+        description: String::from("No output"),
+    });
+    return devices;
+}
+
+fn to_i16(samples: &[f32]) -> Vec<i16> {
+    return samples.iter().map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).collect();
+}
+
+struct AlsaSink {
+    pcm: PCM,
+    channels: usize,
+    paused: bool,
+}
+
+impl AlsaSink {
+    fn open(device: &str, rate: u32, channels: u32) -> Result<AlsaSink, alsa::Error> {
+        let pcm = PCM::new(device, Direction::Playback, true)?;
+        {
+            let hwp = HwParams::any(&pcm)?;
+            hwp.set_channels(channels)?;
+            hwp.set_rate(rate, ValueOr::Nearest)?;
+            hwp.set_format(Format::s16())?;
+            hwp.set_access(Access::RWInterleaved)?;
+            hwp.set_buffer_time_near((BUFFER_SECS * 1_000_000.0) as u32, ValueOr::Nearest)?;
+            pcm.hw_params(&hwp)?;
+        }
+        return Ok(AlsaSink {
+            pcm,
+            channels: channels as usize,
+            paused: false,
+        });
+    }
+}
+
+impl Sink for AlsaSink {
+    fn write(&mut self, samples: &[f32]) -> Result<usize,String> {
+        if self.paused {
+            return Ok(0);
+        }
+        let available = match self.pcm.avail_update() {
+            Ok(a) => a.max(0) as usize,
+            Err(e) => {
+                // an underrun, start over
+                self.pcm.try_recover(e, true).map_err(|e| e.to_string())?;
+                return Ok(0);
+            },
+        };
+        let frames = available.min(samples.len() / self.channels);
+        if frames == 0 {
+            return Ok(0);
+        }
+        let io = self.pcm.io_i16().map_err(|e| e.to_string())?;
+        return match io.writei(&to_i16(&samples[..frames * self.channels])) {
+            Ok(n) => Ok(n),
+            Err(e) => {
+                self.pcm.try_recover(e, true).ok();
+                Ok(0)
+            },
+        }
+    }
+
+    fn set_pause(&mut self, pause: bool) {
+        // not every device can pause, stopping and starting over works everywhere
+        if pause {
+            self.pcm.drop().ok();
+        }
+        else {
+            self.pcm.prepare().ok();
+        }
+        self.paused = pause;
+    }
+
+    fn clear(&mut self) {
+        self.pcm.drop().ok();
+        self.pcm.prepare().ok();
+    }
+
+    fn set_format(&mut self, _rate: u32, _channels: u32) -> Result<bool,String> {
+        return Ok(false);
+    }
+}
+
+// the most sample data the sizes in a wav header can count
+const MAX_WAV_DATA : u64 = u32::MAX as u64 - 36;
+
+// 16 bit pcm, the sizes in the header are filled in when it's closed
+struct WavFile {
+    writer: BufWriter<File>,
+    rate: u32,
+    channels: u32,
+    data_len: u64,
+}
+
+impl WavFile {
+    // an existing file is never written over
+    fn create(path: &str, rate: u32, channels: u32) -> Result<WavFile, std::io::Error> {
+        let mut writer = BufWriter::new(OpenOptions::new().write(true).create_new(true).open(path)?);
+        let block_align = channels * 2;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // pcm
+        writer.write_all(&(channels as u16).to_le_bytes())?;
+        writer.write_all(&rate.to_le_bytes())?;
+        writer.write_all(&(rate * block_align).to_le_bytes())?;
+        writer.write_all(&(block_align as u16).to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        return Ok(WavFile { writer, rate, channels, data_len: 0 });
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), std::io::Error> {
+        let len = samples.len() as u64 * 2;
+        if self.data_len + len > MAX_WAV_DATA {
+            return Err(std::io::Error::other("the wav file is full"));
+        }
+        for sample in to_i16(samples) {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += len;
+        return Ok(());
+    }
+
+    fn finish(&mut self) -> Result<(), std::io::Error> {
+        // can't be more than MAX_WAV_DATA, write() stops there
+        let data_len = self.data_len as u32;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_len.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        return self.writer.flush();
+    }
+}
+
+impl Drop for WavFile {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+// Takes samples as fast as a sound card would, so songs take as long as they should. Writes
+// them to a wav file if it has one.
+struct PacedSink {
+    rate: f64,
+    channels: usize,
+    start: Instant,
+    frames: u64, // written since start
+    paused_at: Option<Instant>,
+    wav: Option<WavFile>,
+}
+
+impl PacedSink {
+    fn new(rate: u32, channels: u32, wav: Option<WavFile>) -> PacedSink {
+        return PacedSink {
+            rate: rate as f64,
+            channels: channels as usize,
+            start: Instant::now(),
+            frames: 0,
+            paused_at: None,
+            wav,
+        }
+    }
+}
+
+impl Sink for PacedSink {
+    fn write(&mut self, samples: &[f32]) -> Result<usize,String> {
+        if self.paused_at.is_some() {
+            return Ok(0);
+        }
+        let due = ((self.start.elapsed().as_secs_f64() + BUFFER_SECS) * self.rate) as u64;
+        let frames = (due.saturating_sub(self.frames) as usize).min(samples.len() / self.channels);
+        match &mut self.wav {
+            Some(w) => { w.write(&samples[..frames * self.channels]).map_err(|e| e.to_string())?; },
+            None => {},
+        }
+        self.frames += frames as u64;
+        return Ok(frames);
+    }
+
+    fn set_pause(&mut self, pause: bool) {
+        match (pause, self.paused_at) {
+            (true, None) => { self.paused_at = Some(Instant::now()); },
+            (false, Some(t)) => {
+                // the time spent paused doesn't count
+                self.start += t.elapsed();
+                self.paused_at = None;
+            },
+            _ => {},
+        }
+    }
+
+    fn clear(&mut self) {
+        self.start = Instant::now();
+        self.frames = 0;
+        if self.paused_at.is_some() {
+            self.paused_at = Some(Instant::now());
+        }
+    }
+
+    // The wav file is never started over, and its header only has room for one rate and channel
+    // count. Samples of another format can't go into it
+    fn set_format(&mut self, rate: u32, channels: u32) -> Result<bool,String> {
+        match &self.wav {
+            Some(w) if w.rate != rate || w.channels != channels => {
+                return Err(format!("the wav file is {} Hz with {} channels, can't add {} Hz with {} channels to it",
+                    w.rate, w.channels, rate, channels));
+            },
+            _ => {},
+        }
+        self.rate = rate as f64;
+        self.channels = channels as usize;
+        self.clear();
+        return Ok(true);
+    }
+}
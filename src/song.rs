@@ -1,3 +1,5 @@
+#![allow(clippy::needless_return, clippy::redundant_slicing)]
+
 use std::io;
 use std::io::prelude::*;
 use std::fs::File;
@@ -8,6 +10,7 @@ pub struct Song {
     pub title: String,
     pub album: String,
     pub artist: String,
+    pub album_artist: String, // same as artist unless the album is a compilation
     pub genre: String,
     pub year: i64,
     pub track_num: i64,
@@ -16,13 +19,20 @@ pub struct Song {
     pub lyrics: String,
     pub hash: String,
     pub size: i64,
+    // replaygain, gains in dB and peaks as linear amplitude
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
 }
 
 impl Song {
+    #[allow(clippy::inherent_to_string, clippy::useless_conversion)]
     pub fn to_string(&self) -> String {
         return String::from(format!("{} - {} {}, {}; {}s", &self.track_num, &self.title, &self.album,&self.year,&self.duration));
     }
 
+    #[allow(dead_code)]
     pub fn hash(&mut self) -> Result<(), io::Error> {
         self.hash = song_hash(&self.path)?;
         return Ok(());
@@ -0,0 +1,216 @@
+use std::process::{Command, Stdio};
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::song::Song;
+
+// replaygain 2.0 reference level, in LUFS
+const REFERENCE_LOUDNESS : f64 = -18.0;
+
+// what the analysis job reports back
+pub enum Update {
+    Progress { done: usize, total: usize },
+    Analyzed(Box<Song>), // the song with its replaygain filled in
+    Failed(String, String), // path of a song that couldn't be measured or tagged, and why
+    Done,
+}
+
+struct Loudness {
+    integrated: f64, // in LUFS
+    peak: f64,       // true peak, linear
+}
+
+// Measure every song (EBU R128 integrated loudness and true peak) and work out track and album
+// gain from it. Runs on its own thread since decoding a library takes a while. Songs are only
+// read, storing the results is up to whoever gets the updates. With write_tags the gains are
+// also written into the files. Album gain is approximate, see album_loudness. Fails right away
+// when the tools the job needs are not installed.
+pub fn spawn(songs: Vec<Song>, write_tags: bool) -> Result<Receiver<Update>, String> {
+    if !installed("ffmpeg", "-version") {
+        return Err(String::from("ffmpeg not found, it's needed to measure loudness"));
+    }
+    if write_tags && !installed("vorbiscomment", "-V") {
+        return Err(String::from("vorbiscomment not found, it's needed to write replaygain tags"));
+    }
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let total = songs.len();
+        let mut done = 0;
+
+        // album gain needs the whole album measured first
+        let mut albums : Vec<Vec<Song>> = Vec::new();
+        for song in songs {
+            match albums.iter_mut().find(|a| a[0].album_artist == song.album_artist && a[0].album == song.album) {
+                Some(a) => a.push(song),
+                None => albums.push(vec![song]),
+            }
+        }
+
+        for album in albums {
+            let mut measured : Vec<(Song, Loudness)> = Vec::new();
+            for song in album {
+                match measure(&song.path) {
+                    Some(l) => measured.push((song, l)),
+                    None => { sender.send(Update::Failed(song.path.clone(), String::from("cannot measure loudness"))).ok(); },
+                }
+                done += 1;
+                if sender.send(Update::Progress { done, total }).is_err() {
+                    return; // nobody is listening anymore
+                }
+            }
+
+            let (album_loudness, album_peak) = album_loudness(&measured);
+            for (mut song, loudness) in measured {
+                song.track_gain = Some(round(REFERENCE_LOUDNESS - loudness.integrated));
+                song.track_peak = Some(loudness.peak);
+                song.album_gain = Some(round(REFERENCE_LOUDNESS - album_loudness));
+                song.album_peak = Some(album_peak);
+                if write_tags {
+                    match write_replaygain_tags(&song) {
+                        Ok(_) => {},
+                        Err(e) => { sender.send(Update::Failed(song.path.clone(), e.to_string())).ok(); },
+                    }
+                }
+                sender.send(Update::Analyzed(Box::new(song))).ok();
+            }
+        }
+        sender.send(Update::Done).ok();
+    });
+    return Ok(receiver);
+}
+
+// whether the program can be started at all, its output doesn't matter
+fn installed(program: &str, version_flag: &str) -> bool {
+    return Command::new(program)
+        .arg(version_flag)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok();
+}
+
+fn round(gain: f64) -> f64 {
+    return (gain * 100.0).round() / 100.0;
+}
+
+// Loudness of the tracks as if they were played back to back. This is an approximation, not an
+// R128 measurement of the whole album: the integrated loudness of each track is turned back
+// into energy and averaged, weighted by how long the track is. Gating is per track, so quiet
+// passages count a bit differently than they would when measuring the album in one go, but it
+// saves decoding everything again.
+fn album_loudness(measured: &[(Song, Loudness)]) -> (f64, f64) {
+    let mut energy = 0.0;
+    let mut length = 0.0;
+    let mut peak : f64 = 0.0;
+    for (song, loudness) in measured {
+        let duration = if song.duration > 0.0 { song.duration } else { 1.0 };
+        energy += duration * 10f64.powf(loudness.integrated / 10.0);
+        length += duration;
+        peak = peak.max(loudness.peak);
+    }
+    if length == 0.0 {
+        return (REFERENCE_LOUDNESS, 1.0);
+    }
+    return (10.0 * (energy / length).log10(), peak);
+}
+
+// decode the file with ffmpeg and read the summary of its ebur128 filter
+fn measure(filepath: &str) -> Option<Loudness> {
+    let command = Command::new("ffmpeg")
+        .args([
+            "-nostats",
+            "-hide_banner",
+            "-i", filepath,
+            "-filter_complex", "ebur128=peak=true",
+            "-f", "null",
+            "-",
+        ])
+        .output()
+        .ok()?;
+    if !command.status.success() {
+        return None;
+    }
+
+    // the summary is printed last, after the per block measurements
+    let output = String::from_utf8_lossy(&command.stderr);
+    let summary = &output[output.rfind("Summary:")?..];
+    let mut integrated : Option<f64> = None;
+    let mut peak : Option<f64> = None;
+    for line in summary.lines() {
+        let line = line.trim();
+        match (line.strip_prefix("I:"), line.strip_prefix("Peak:")) {
+            (Some(value), _) => { integrated = first_number(value); },
+            // in dBFS
+            (_, Some(value)) => { peak = first_number(value).map(|db| 10f64.powf(db / 20.0)); },
+            _ => {},
+        }
+    }
+
+    return Some(Loudness {
+        integrated: integrated?,
+        peak: peak?,
+    });
+}
+
+fn first_number(s: &str) -> Option<f64> {
+    return s.split_whitespace().next()?.parse::<f64>().ok();
+}
+
+// Replace the replaygain comments of an ogg vorbis file, keeping the rest of them. Other files
+// are left alone, the database only takes ogg files anyway
+fn write_replaygain_tags(song: &Song) -> Result<(), io::Error> {
+    let mut magic = [0u8; 4];
+    File::open(&song.path)?.read_exact(&mut magic)?;
+    if &magic != b"OggS" {
+        return Err(io::Error::other("tags can only be written to ogg files"));
+    }
+
+    // -e escapes newlines and backslashes so comments spanning lines come back the same
+    let listed = Command::new("vorbiscomment")
+        .args(["-l", "-R", "-e", &song.path])
+        .output()?;
+    if !listed.status.success() {
+        return Err(io::Error::other("cannot read comments"));
+    }
+
+    let mut comments : Vec<String> = String::from_utf8_lossy(&listed.stdout)
+        .lines()
+        .filter(|l| !l.to_uppercase().starts_with("REPLAYGAIN_"))
+        .map(String::from)
+        .collect();
+    let replaygain = [
+        ("REPLAYGAIN_TRACK_GAIN", song.track_gain.map(|v| format!("{:.2} dB", v))),
+        ("REPLAYGAIN_TRACK_PEAK", song.track_peak.map(|v| format!("{:.6}", v))),
+        ("REPLAYGAIN_ALBUM_GAIN", song.album_gain.map(|v| format!("{:.2} dB", v))),
+        ("REPLAYGAIN_ALBUM_PEAK", song.album_peak.map(|v| format!("{:.6}", v))),
+    ];
+    for (tag, value) in replaygain {
+        match value {
+            Some(v) => comments.push(format!("{}={}", tag, v)),
+            None => {},
+        }
+    }
+
+    // -w takes the new comments from stdin
+    let mut writer = Command::new("vorbiscomment")
+        .args(["-w", "-R", "-e", &song.path])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    match writer.stdin.take() {
+        Some(mut stdin) => {
+            for comment in comments {
+                writeln!(stdin, "{}", comment)?;
+            }
+        },
+        None => {},
+    }
+    if !writer.wait()?.success() {
+        return Err(io::Error::other("cannot write comments"));
+    }
+    return Ok(());
+}
@@ -1,15 +1,17 @@
+#![allow(clippy::needless_return, clippy::single_match, clippy::len_zero, clippy::redundant_slicing)]
+
 use std::process::Command;
 use std::{io,fs};
-use json;
-use sqlite;
 use walkdir::WalkDir;
 
 use crate::song::Song;
 use crate::song;
 
 pub mod query;
+pub mod loudness;
 pub use self::query::Query;
 
+#[allow(clippy::needless_borrow, clippy::manual_unwrap_or)]
 pub fn get_meta(filepath: &str) -> Result<Song, io::Error> {
     // let comm = Command::new("ls").args([".","src"]).output().expect("lala");
     // println!("{}",String::from_utf8_lossy(&comm.stdout));
@@ -45,6 +47,9 @@ pub fn get_meta(filepath: &str) -> Result<Song, io::Error> {
     let artist = match tags["Performer"].as_str() {
         Some(s) => String::from(s), None => String::from("unknown artist"),
     };
+    let album_artist = match tags["Album_Performer"].as_str() {
+        Some(s) => String::from(s), None => artist.clone(),
+    };
     let genre = match tags["Genre"] .as_str() {
         Some(s) => String::from(s), None => String::from("unknown genre")
     };
@@ -63,11 +68,19 @@ pub fn get_meta(filepath: &str) -> Result<Song, io::Error> {
         Some(s) => match s.parse::<i64>() { Ok(i) => i, Err(_) => -1 },
         None => -1
     };
+    // replaygain shows up in different places depending on the tag format
+    let general_extra = &tags["extra"];
+    let audio = &parsed["media"]["track"][1];
+    let track_gain = parse_gain(&general_extra["REPLAYGAIN_TRACK_GAIN"]).or(parse_gain(&audio["ReplayGain_Gain"]));
+    let track_peak = parse_gain(&general_extra["REPLAYGAIN_TRACK_PEAK"]).or(parse_gain(&audio["ReplayGain_Peak"]));
+    let album_gain = parse_gain(&general_extra["REPLAYGAIN_ALBUM_GAIN"]).or(parse_gain(&tags["Album_ReplayGain_Gain"]));
+    let album_peak = parse_gain(&general_extra["REPLAYGAIN_ALBUM_PEAK"]).or(parse_gain(&tags["Album_ReplayGain_Peak"]));
     let size = fs::metadata(filepath)?.len() as i64;
     let song = Song{
         title,
         album,
         artist,
+        album_artist,
         genre,
         year, 
         track_num,
@@ -77,11 +90,21 @@ pub fn get_meta(filepath: &str) -> Result<Song, io::Error> {
         // hash: song_hash(&filepath)?, // expensive, do it only when needed 
         hash: String::from(""),
         size,
+        track_gain,
+        track_peak,
+        album_gain,
+        album_peak,
     };
 
     return Ok(song);
 }
 
+// "-6.50 dB" or "0.988312" to a number
+fn parse_gain(value: &json::JsonValue) -> Option<f64> {
+    let s = value.as_str()?;
+    return s.trim().trim_end_matches("dB").trim().parse::<f64>().ok();
+}
+
 pub fn get_meta_dir(dir: &str) -> Vec<Song> {
     // walk through every file in this directory and its subdirectories
     let mut songs : Vec<Song> = vec![];
@@ -101,6 +124,8 @@ pub fn get_meta_dir(dir: &str) -> Vec<Song> {
     return songs;
 }
 
+// the navigator only lists some of them so far
+#[allow(dead_code)]
 pub enum Table {
     Title,
     Album,
@@ -114,6 +139,7 @@ pub enum Table {
     Size,
 }
 
+#[allow(clippy::to_string_trait_impl)]
 impl ToString for Table {
     // The string is the actual name of the corresponding table in the database
     fn to_string(&self) -> String {
@@ -133,6 +159,7 @@ impl ToString for Table {
 }
 
 pub struct SongDB {
+    #[allow(dead_code)]
     pub database_path: String,
     connection: sqlite::Connection,
 }
@@ -142,7 +169,8 @@ impl SongDB {
     // add a song to the database
     pub fn add(&self, song: &Song) -> Result<(),sqlite::Error>{
         // insert into song relation
-        let mut statement = self.connection.prepare("insert into song values (:title,:album,:tracknum,:artist,:genre,:duration,:year,:path,:hash,:size)")?;
+        let mut statement = self.connection.prepare("insert into song (Title,Album,TrackNumber,Artist,Genre,Duration,Year,Path,Version,Size,TrackGain,TrackPeak,AlbumGain,AlbumPeak,AlbumArtist)
+            values (:title,:album,:tracknum,:artist,:genre,:duration,:year,:path,:hash,:size,:trackgain,:trackpeak,:albumgain,:albumpeak,:albumartist)")?;
         statement.bind_by_name(":title", &song.title[..])?;
         statement.bind_by_name(":album", &song.album[..])?;
         statement.bind_by_name(":tracknum", song.track_num)?;
@@ -155,6 +183,11 @@ impl SongDB {
         statement.bind_by_name(":hash", &hash[..])?;
         statement.bind_by_name(":path", &song.path[..])?;
         statement.bind_by_name(":size", song.size)?;
+        statement.bind_by_name(":trackgain", song.track_gain)?;
+        statement.bind_by_name(":trackpeak", song.track_peak)?;
+        statement.bind_by_name(":albumgain", song.album_gain)?;
+        statement.bind_by_name(":albumpeak", song.album_peak)?;
+        statement.bind_by_name(":albumartist", &song.album_artist[..])?;
         let _ = statement.next(); // handle error later
 
         // insert into lyrics relation
@@ -183,6 +216,7 @@ impl SongDB {
         return Ok(());
     }
 
+    #[allow(dead_code)]
     pub fn update(&self, title: &str, album: &str, song: &Song) -> Result<(),sqlite::Error>{
         // insert into song relation
         let mut statement = self.connection.prepare("update song set TrackNumber = :tracknum, Artist = :artist, Genre = :genre, Duration = :duration Year = :year, Path = :path, Version = :hash, Size = :size where Title = :title and Album = :album")?;
@@ -208,23 +242,64 @@ impl SongDB {
         return Ok(());
     }
 
+    // store the replaygain of a song, e.g. after measuring it
+    pub fn set_replaygain(&self, song: &Song) -> Result<(),sqlite::Error> {
+        let mut statement = self.connection.prepare("update song set TrackGain = :trackgain, TrackPeak = :trackpeak, AlbumGain = :albumgain, AlbumPeak = :albumpeak where Title = :title and Album = :album")?;
+        statement.bind_by_name(":title", &song.title[..])?;
+        statement.bind_by_name(":album", &song.album[..])?;
+        statement.bind_by_name(":trackgain", song.track_gain)?;
+        statement.bind_by_name(":trackpeak", song.track_peak)?;
+        statement.bind_by_name(":albumgain", song.album_gain)?;
+        statement.bind_by_name(":albumpeak", song.album_peak)?;
+        statement.next()?;
+        return Ok(());
+    }
+
+    // mark a song that couldn't be played, prune_db removes it
+    pub fn flag_broken(&self, path: &str) -> Result<(),sqlite::Error> {
+        let mut statement = self.connection.prepare("update song set Broken = 1 where Path = :path")?;
+        statement.bind_by_name(":path", &path[..])?;
+        statement.next()?;
+        return Ok(());
+    }
+
     // go through the db and remove entries that don't exist in the fs
     // does not read if it's actually the same file
+    // songs flagged as broken are removed too, if they can't be opened anymore
     pub fn prune_db(&self) -> Result<(),sqlite::Error> {
         // get a list paths that should have a song
         let mut songs : Vec<(String,String,String)> = vec![]; // Title,Album,Path
+        let mut missing : Vec<(String,String)> = vec![];
+        let mut flagged : Vec<(String,String,String)> = vec![];
         
         // sql query
-        let mut statement = self.connection.prepare("select Title,Album,Path from song")?;
+        let mut statement = self.connection.prepare("select Title,Album,Path,Broken from song")?;
         while let sqlite::State::Row = statement.next()? {
            let title = statement.read::<String>(0)?; 
            let album = statement.read::<String>(1)?; 
            let path = statement.read::<String>(2)?; 
-           songs.push((title,album,path));
+           let broken = statement.read::<Option<i64>>(3)?.unwrap_or(0) != 0;
+           if broken {
+               flagged.push((title,album,path));
+           }
+           else {
+               songs.push((title,album,path));
+           }
+        }
+
+        // the flag only says playing it went wrong once, the file might be fine
+        for song in flagged {
+            match fs::File::open(&song.2) {
+                Ok(_) => {
+                    let mut statement = self.connection.prepare("update song set Broken = 0 where Path = :path")?;
+                    statement.bind_by_name(":path", &song.2[..])?;
+                    statement.next()?;
+                },
+                Err(_) => missing.push((song.0,song.1)),
+            }
         }
 
         // check to see if each song path still exists
-        let mut missing : Vec<(String,String)> = vec![];
         for song in songs {
             match fs::metadata(song.2) {
                 Ok(_) => {/*println!("it's there")*/},
@@ -261,6 +336,35 @@ impl SongDB {
         }
     }
 
+    pub fn get_by_path(&self, path: &str) -> Option<Song> {
+        let mut statement = self.connection.prepare("select * from song where Path = :path").ok()?;
+        statement.bind_by_name(":path", &path[..]).ok()?;
+
+        return match self.query(&mut statement) {
+            Some(mut x) => x.pop(),
+            None => None
+        }
+    }
+
+    // look up songs by their tags instead of their path. artist and album are only used to narrow
+    // the search when they are given
+    pub fn search_meta(&self, title: &str, artist: Option<&str>, album: Option<&str>) -> Vec<Song> {
+        let mut sql = String::from("select * from song where Title = :title");
+        if artist.is_some() { sql.push_str(" and Artist = :artist"); }
+        if album.is_some() { sql.push_str(" and Album = :album"); }
+        let mut statement = match self.connection.prepare(sql).ok() {
+            Some(x) => x,
+            None => {return Vec::new()}
+        };
+        statement.bind_by_name(":title", &title[..]).ok();
+        match artist { Some(v) => {statement.bind_by_name(":artist", &v[..]).ok();}, None => {}}
+        match album { Some(v) => {statement.bind_by_name(":album", &v[..]).ok();}, None => {}}
+        return match self.query(&mut statement) {
+            Some(x) => x,
+            None => {return Vec::new()}
+        }
+    }
+
     pub fn search_all(&self) -> Vec<Song> {
         let mut statement = match self.connection.prepare("select * from song").ok() {
             Some(x) => x,
@@ -320,10 +424,17 @@ impl SongDB {
            let path = statement.read::<String>(7).ok()?; 
            let hash = statement.read::<String>(8).ok()?; 
            let size = statement.read::<i64>(9).ok()?; 
+           let track_gain = statement.read::<Option<f64>>(10).ok()?;
+           let track_peak = statement.read::<Option<f64>>(11).ok()?;
+           let album_gain = statement.read::<Option<f64>>(12).ok()?;
+           let album_peak = statement.read::<Option<f64>>(13).ok()?;
+           // songs added before the album artist was kept don't have one
+           let album_artist = statement.read::<Option<String>>(15).ok()?.unwrap_or(artist.clone());
            let song = Song{
                title,
                album,
                artist,
+               album_artist,
                genre,
                year, 
                track_num,
@@ -332,6 +443,10 @@ impl SongDB {
                lyrics: String::from("placeholder"), // currently not querying in this function 
                hash,
                size,
+               track_gain,
+               track_peak,
+               album_gain,
+               album_peak,
            };
            song_list.push(song);
         }
@@ -349,12 +464,184 @@ impl SongDB {
         return Ok(results);
     }
 
+    // saves the song queue in the order it's shown. order holds each song's place in the play
+    // order and playing is the index of the song that's playing
+    pub fn save_queue(&self, songs: &[Song], order: &[u32], playing: Option<u32>) -> Result<(),sqlite::Error> {
+        self.connection.execute("begin transaction;")?;
+        // the old queue stays if anything goes wrong, and the next save can start over
+        match self.write_queue(songs, order, playing) {
+            Ok(_) => self.connection.execute("commit;")?,
+            Err(e) => {
+                self.connection.execute("rollback;").ok();
+                return Err(e);
+            },
+        }
+        return Ok(());
+    }
+
+    fn write_queue(&self, songs: &[Song], order: &[u32], playing: Option<u32>) -> Result<(),sqlite::Error> {
+        self.connection.execute("delete from queue;")?;
+        for (i, song) in songs.iter().enumerate() {
+            let play_order = order.iter().position(|v| *v == i as u32).unwrap_or(i);
+            let mut statement = self.connection.prepare("insert into queue (Position,PlayOrder,Playing,Title,Album,Path) values (:position,:playorder,:playing,:title,:album,:path)")?;
+            statement.bind_by_name(":position", i as i64)?;
+            statement.bind_by_name(":playorder", play_order as i64)?;
+            statement.bind_by_name(":playing", (playing == Some(i as u32)) as i64)?;
+            statement.bind_by_name(":title", &song.title[..])?;
+            statement.bind_by_name(":album", &song.album[..])?;
+            statement.bind_by_name(":path", &song.path[..])?;
+            statement.next()?;
+        }
+        return Ok(());
+    }
+
+    // the saved queue as (song, place in play order, is playing). Songs that aren't in the
+    // database anymore are left out
+    pub fn load_queue(&self) -> Vec<(Song,i64,bool)> {
+        let mut entries : Vec<(Song,i64,bool)> = Vec::new();
+        let mut rows : Vec<(String,String,Option<String>,i64,bool)> = Vec::new();
+        let mut statement = match self.connection.prepare("select Title,Album,Path,PlayOrder,Playing from queue order by Position").ok() {
+            Some(x) => x,
+            None => {return entries}
+        };
+        while let Ok(sqlite::State::Row) = statement.next() {
+            let title = statement.read::<String>(0).unwrap_or_default();
+            let album = statement.read::<String>(1).unwrap_or_default();
+            let path = statement.read::<Option<String>>(2).unwrap_or_default();
+            let play_order = statement.read::<i64>(3).unwrap_or(0);
+            let playing = statement.read::<i64>(4).unwrap_or(0) != 0;
+            rows.push((title,album,path,play_order,playing));
+        }
+        for row in rows {
+            // queues saved before the path was kept only have title and album
+            let song = match &row.2 {
+                Some(path) => self.get_by_path(path),
+                None => self.get_meta(&row.0, &row.1),
+            };
+            match song {
+                Some(song) => entries.push((song,row.3,row.4)),
+                None => {},
+            }
+        }
+        return entries;
+    }
+
+    // small key/value store for things that should survive a restart
+    pub fn set_state(&self, key: &str, value: &str) -> Result<(),sqlite::Error> {
+        let mut statement = self.connection.prepare("insert or replace into state values (:key,:value)")?;
+        statement.bind_by_name(":key", &key[..])?;
+        statement.bind_by_name(":value", &value[..])?;
+        statement.next()?;
+        return Ok(());
+    }
+
+    pub fn get_state(&self, key: &str) -> Option<String> {
+        let mut statement = self.connection.prepare("select Value from state where Key = :key").ok()?;
+        statement.bind_by_name(":key", &key[..]).ok()?;
+        return match statement.next().ok()? {
+            sqlite::State::Row => statement.read::<String>(0).ok(),
+            sqlite::State::Done => None,
+        }
+    }
+
+    // remembered playback speeds. a whole album is stored with an empty title
+    pub fn set_speed(&self, title: &str, album: &str, speed: f64) -> Result<(),sqlite::Error> {
+        let mut statement = self.connection.prepare("insert or replace into speed values (:title,:album,:speed)")?;
+        statement.bind_by_name(":title", &title[..])?;
+        statement.bind_by_name(":album", &album[..])?;
+        statement.bind_by_name(":speed", speed)?;
+        statement.next()?;
+        return Ok(());
+    }
+
+    pub fn get_speed(&self, title: &str, album: &str) -> Option<f64> {
+        let mut statement = self.connection.prepare("select Speed from speed where Title = :title and Album = :album").ok()?;
+        statement.bind_by_name(":title", &title[..]).ok()?;
+        statement.bind_by_name(":album", &album[..]).ok()?;
+        return match statement.next().ok()? {
+            sqlite::State::Row => statement.read::<f64>(0).ok(),
+            sqlite::State::Done => None,
+        }
+    }
+
+    // the a-b loop saved for a song, None forgets it
+    pub fn set_loop(&self, title: &str, album: &str, ab_loop: Option<(f64,f64)>) -> Result<(),sqlite::Error> {
+        let mut statement = match ab_loop {
+            Some(_) => self.connection.prepare("insert or replace into loops values (:title,:album,:start,:end)")?,
+            None => self.connection.prepare("delete from loops where Title = :title and Album = :album")?,
+        };
+        statement.bind_by_name(":title", &title[..])?;
+        statement.bind_by_name(":album", &album[..])?;
+        match ab_loop {
+            Some((start, end)) => {
+                statement.bind_by_name(":start", start)?;
+                statement.bind_by_name(":end", end)?;
+            },
+            None => {},
+        }
+        statement.next()?;
+        return Ok(());
+    }
+
+    pub fn get_loop(&self, title: &str, album: &str) -> Option<(f64,f64)> {
+        let mut statement = self.connection.prepare("select Start,End from loops where Title = :title and Album = :album").ok()?;
+        statement.bind_by_name(":title", &title[..]).ok()?;
+        statement.bind_by_name(":album", &album[..]).ok()?;
+        return match statement.next().ok()? {
+            sqlite::State::Row => Some((statement.read::<f64>(0).ok()?, statement.read::<f64>(1).ok()?)),
+            sqlite::State::Done => None,
+        }
+    }
+
+    // where to pick a long song up again, None forgets it
+    pub fn set_resume(&self, title: &str, album: &str, position: Option<f64>) -> Result<(),sqlite::Error> {
+        let mut statement = match position {
+            Some(_) => self.connection.prepare("insert or replace into resume values (:title,:album,:position)")?,
+            None => self.connection.prepare("delete from resume where Title = :title and Album = :album")?,
+        };
+        statement.bind_by_name(":title", &title[..])?;
+        statement.bind_by_name(":album", &album[..])?;
+        match position {
+            Some(p) => { statement.bind_by_name(":position", p)?; },
+            None => {},
+        }
+        statement.next()?;
+        return Ok(());
+    }
+
+    pub fn get_resume(&self, title: &str, album: &str) -> Option<f64> {
+        let mut statement = self.connection.prepare("select Position from resume where Title = :title and Album = :album").ok()?;
+        statement.bind_by_name(":title", &title[..]).ok()?;
+        statement.bind_by_name(":album", &album[..]).ok()?;
+        return match statement.next().ok()? {
+            sqlite::State::Row => statement.read::<f64>(0).ok(),
+            sqlite::State::Done => None,
+        }
+    }
+
+    // titles of the songs that have a resume position
+    pub fn get_resume_titles(&self) -> Vec<String> {
+        let mut titles : Vec<String> = Vec::new();
+        let mut statement = match self.connection.prepare("select distinct Title from resume order by Title").ok() {
+            Some(x) => x,
+            None => {return titles}
+        };
+        while let Ok(sqlite::State::Row) = statement.next() {
+            match statement.read::<String>(0) {
+                Ok(t) => titles.push(t),
+                Err(_) => {},
+            }
+        }
+        return titles;
+    }
+
     // checks if file of song in databse has changed
     // You can choose what to check (file size or checksum). If both are checked, size is checked
     // first.
     // returns true if the file size is inconsistent with db's entry of the song
     // returns true if the sha1 checksums are different
     // false if checksums are the same and the filesize is the same
+    #[allow(clippy::collapsible_if)]
     pub fn check_change(&self, title: &str, album: &str, check_size: bool, check_hash: bool) -> Option<bool> {
         let mut statement = self.connection.prepare("select Path,Size,Version from song where Title = :title and Album = :album").ok()?;
         statement.bind_by_name(":title", &title[..]).ok()?;
@@ -395,12 +682,82 @@ pub fn open(db_path: &str) -> Result<SongDB,sqlite::Error> {
     songdb.connection.execute(
         "
         create table if not exists song (Title TEXT, Album TEXT, TrackNumber INTEGER, Artist TEXT, Genre TEXT, Duration DECIMAL, Year INTEGER, Path TEXT, Version CHAR(16), Size INTEGER,
+            TrackGain DECIMAL, TrackPeak DECIMAL, AlbumGain DECIMAL, AlbumPeak DECIMAL, Broken INTEGER, AlbumArtist TEXT,
             CONSTRAINT PK_Song PRIMARY KEY (Title, Album));
         create table if not exists lyrics (Title TEXT NOT NULL, Album TEXT NOT NULL, Lyrics TEXT,
             FOREIGN KEY(Title) REFERENCES songs(Title),
             FOREIGN KEY(Album) REFERENCES songs(Album));
+        create table if not exists queue (Position INTEGER, PlayOrder INTEGER, Playing INTEGER, Title TEXT, Album TEXT, Path TEXT);
+        create table if not exists state (Key TEXT PRIMARY KEY, Value TEXT);
+        create table if not exists speed (Title TEXT, Album TEXT, Speed DECIMAL,
+            CONSTRAINT PK_Speed PRIMARY KEY (Title, Album));
+        create table if not exists resume (Title TEXT, Album TEXT, Position DECIMAL,
+            CONSTRAINT PK_Resume PRIMARY KEY (Title, Album));
+        create table if not exists loops (Title TEXT, Album TEXT, Start DECIMAL, End DECIMAL,
+            CONSTRAINT PK_Loops PRIMARY KEY (Title, Album));
         "
     )?;
+    // columns added since the song table was first made. fails for the ones that are already there
+    for column in ["TrackGain", "TrackPeak", "AlbumGain", "AlbumPeak"] {
+        songdb.connection.execute(format!("alter table song add column {} DECIMAL", column)).ok();
+    }
+    songdb.connection.execute("alter table song add column Broken INTEGER").ok();
+    songdb.connection.execute("alter table song add column AlbumArtist TEXT").ok();
+    songdb.connection.execute("alter table queue add column Path TEXT").ok();
     return Ok(songdb);
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn song(title: &str, album: &str, path: &str) -> Song {
+        return Song {
+            title: String::from(title),
+            album: String::from(album),
+            artist: String::from("artist"),
+            album_artist: String::from("artist"),
+            genre: String::new(),
+            year: 0,
+            track_num: 0,
+            duration: 180.0,
+            path: String::from(path),
+            lyrics: String::new(),
+            hash: String::new(),
+            size: 0,
+            track_gain: None,
+            track_peak: None,
+            album_gain: None,
+            album_peak: None,
+        };
+    }
+
+    #[test]
+    fn queue_is_restored_by_path() {
+        let db = open(":memory:").unwrap();
+        let songs = vec![song("intro", "first", "first/intro.ogg"), song("intro", "second", "second/intro.ogg")];
+        for s in songs.iter() {
+            db.add(s).unwrap();
+        }
+        db.save_queue(&songs, &[1, 0], Some(1)).unwrap();
+        let restored : Vec<(String,i64,bool)> = db.load_queue().into_iter().map(|e| (e.0.path, e.1, e.2)).collect();
+        assert_eq!(restored, vec![
+            (String::from("first/intro.ogg"), 1, false),
+            (String::from("second/intro.ogg"), 0, true),
+        ]);
+    }
+
+    #[test]
+    fn failed_queue_save_is_rolled_back() {
+        let db = open(":memory:").unwrap();
+        let songs = vec![song("intro", "first", "first/intro.ogg")];
+        db.add(&songs[0]).unwrap();
+        db.connection.execute("drop table queue;").unwrap();
+        assert!(db.save_queue(&songs, &[0], None).is_err());
+        // nothing is left half done, so the next save works
+        db.connection.execute("create table queue (Position INTEGER, PlayOrder INTEGER, Playing INTEGER, Title TEXT, Album TEXT, Path TEXT);").unwrap();
+        db.save_queue(&songs, &[0], None).unwrap();
+        assert_eq!(db.load_queue().len(), 1);
+    }
+}
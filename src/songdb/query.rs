@@ -1,3 +1,5 @@
+// not every field is searched by yet
+#[allow(dead_code)]
 pub struct Query {
     pub title:      Option<String>,
     pub album:      Option<String>,
@@ -0,0 +1,363 @@
+#![allow(clippy::needless_return, clippy::single_match, clippy::len_zero)]
+
+use std::io;
+use std::fs;
+use std::env;
+use std::path::{Path, PathBuf, Component};
+use quick_xml::events::Event;
+use quick_xml::reader::Reader;
+use quick_xml::escape::escape;
+
+use crate::song::Song;
+use crate::songdb::SongDB;
+
+// rel prefix for the tags xspf doesn't have an element for
+const META_REL: &str = "https://github.com/PitEG/rumu/";
+
+// Read an xspf playlist and find the songs it refers to.
+// A track is matched by its location first. If the file isn't in the database (moved library,
+// another machine), it's looked up by title/artist/album instead. Tracks that can't be found
+// anywhere are skipped.
+pub fn import(db: &SongDB, filepath: &str) -> Result<Vec<Song>, io::Error> {
+    let content = fs::read_to_string(filepath)?;
+    let tracks = parse(&content)?;
+
+    let mut songs : Vec<Song> = Vec::new();
+    for track in tracks {
+        match find_song(db, &track) {
+            Some(s) => songs.push(s),
+            None => {},
+        }
+    }
+    return Ok(songs);
+}
+
+// Write songs as an xspf playlist, keeping all of their metadata
+pub fn export(filepath: &str, songs: &[Song]) -> Result<(), io::Error> {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    out.push_str("  <trackList>\n");
+    for song in songs {
+        out.push_str("    <track>\n");
+        push_element(&mut out, "location", &path_to_uri(&song.path));
+        push_element(&mut out, "title", &song.title);
+        push_element(&mut out, "creator", &song.artist);
+        push_element(&mut out, "album", &song.album);
+        if song.track_num > 0 {
+            push_element(&mut out, "trackNum", &song.track_num.to_string());
+        }
+        if song.duration > 0.0 {
+            // xspf durations are in milliseconds
+            push_element(&mut out, "duration", &((song.duration * 1000.0) as i64).to_string());
+        }
+        push_meta(&mut out, "genre", &song.genre);
+        push_meta(&mut out, "year", &song.year.to_string());
+        push_meta(&mut out, "hash", &song.hash);
+        push_meta(&mut out, "size", &song.size.to_string());
+        let replaygain = [
+            ("track_gain", song.track_gain),
+            ("track_peak", song.track_peak),
+            ("album_gain", song.album_gain),
+            ("album_peak", song.album_peak),
+        ];
+        for (name, value) in replaygain {
+            match value {
+                Some(v) => push_meta(&mut out, name, &v.to_string()),
+                None => {},
+            }
+        }
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n");
+    out.push_str("</playlist>\n");
+    return fs::write(filepath, out);
+}
+
+fn push_element(out: &mut String, name: &str, value: &str) {
+    out.push_str(&format!("      <{}>{}</{}>\n", name, escape(value), name));
+}
+
+fn push_meta(out: &mut String, name: &str, value: &str) {
+    out.push_str(&format!("      <meta rel=\"{}{}\">{}</meta>\n", META_REL, name, escape(value)));
+}
+
+fn find_song(db: &SongDB, track: &Song) -> Option<Song> {
+    // same file as when it was exported
+    if track.path.len() > 0 {
+        for path in stored_paths(&track.path) {
+            match db.get_by_path(&path) {
+                Some(s) => { return Some(s); },
+                None => {},
+            }
+        }
+    }
+
+    // fall back to the tags, narrowing down as much as the playlist lets us. a title alone
+    // matches too many songs to be the same one
+    if track.title.len() > 0 {
+        let artist = if track.artist.len() > 0 { Some(&track.artist[..]) } else { None };
+        let album = if track.album.len() > 0 { Some(&track.album[..]) } else { None };
+        let attempts = [(artist, album), (artist, None), (None, album)];
+        for (artist, album) in attempts {
+            if artist.is_none() && album.is_none() {
+                continue;
+            }
+            match db.search_meta(&track.title, artist, album).pop() {
+                Some(s) => { return Some(s); },
+                None => {},
+            }
+        }
+    }
+
+    // not in the database, but the file is still where the playlist says it is
+    if track.path.len() > 0 && Path::new(&track.path).is_file() {
+        return Some(track.clone());
+    }
+
+    return None;
+}
+
+// Turns every <track> into a song. Only the fields xspf knows about are filled in.
+fn parse(content: &str) -> Result<Vec<Song>, io::Error> {
+    let mut reader = Reader::from_str(content);
+    reader.trim_text(true);
+
+    let mut tracks : Vec<Song> = Vec::new();
+    let mut track : Option<Song> = None;
+    let mut element = String::new();
+    loop {
+        let event = match reader.read_event() {
+            Ok(e) => e,
+            Err(e) => { return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string())); },
+        };
+        match event {
+            Event::Start(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                if name == "track" {
+                    track = Some(empty_song());
+                }
+                element = name;
+                if element == "meta" {
+                    match e.try_get_attribute("rel") {
+                        Ok(Some(rel)) => {
+                            let rel = rel.unescape_value().unwrap_or_default();
+                            element = match rel.strip_prefix(META_REL) {
+                                Some(v) => format!("meta:{}", v),
+                                None => String::new(),
+                            };
+                        },
+                        _ => { element = String::new(); },
+                    }
+                }
+            },
+            Event::Text(e) => {
+                let text = e.unescape().unwrap_or_default().to_string();
+                match &mut track {
+                    Some(t) => set_field(t, &element, text),
+                    None => {},
+                }
+            },
+            Event::CData(e) => {
+                let text = String::from_utf8_lossy(&e.into_inner()).to_string();
+                match &mut track {
+                    Some(t) => set_field(t, &element, text),
+                    None => {},
+                }
+            },
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"track" {
+                    match track.take() {
+                        Some(t) => tracks.push(t),
+                        None => {},
+                    }
+                }
+                element = String::new();
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+    return Ok(tracks);
+}
+
+fn set_field(song: &mut Song, element: &str, text: String) {
+    match element {
+        // a track may have several locations, the first usable one wins
+        "location" if song.path.len() == 0 => {
+            match uri_to_path(&text) {
+                Some(p) => { song.path = p; },
+                None => {},
+            }
+        },
+        "title" => { song.title = text; },
+        "creator" => { song.artist = text; },
+        "album" => { song.album = text; },
+        "trackNum" => { song.track_num = text.parse::<i64>().unwrap_or(-1); },
+        "duration" => { song.duration = text.parse::<f64>().map(|ms| ms / 1000.0).unwrap_or(-1.0); },
+        "meta:genre" => { song.genre = text; },
+        "meta:year" => { song.year = text.parse::<i64>().unwrap_or(-1); },
+        "meta:hash" => { song.hash = text; },
+        "meta:size" => { song.size = text.parse::<i64>().unwrap_or(0); },
+        "meta:track_gain" => { song.track_gain = text.parse::<f64>().ok(); },
+        "meta:track_peak" => { song.track_peak = text.parse::<f64>().ok(); },
+        "meta:album_gain" => { song.album_gain = text.parse::<f64>().ok(); },
+        "meta:album_peak" => { song.album_peak = text.parse::<f64>().ok(); },
+        _ => {},
+    }
+}
+
+fn empty_song() -> Song {
+    return Song {
+        title: String::new(),
+        album: String::new(),
+        artist: String::new(),
+        album_artist: String::new(),
+        genre: String::from("unknown genre"),
+        year: -1,
+        track_num: -1,
+        duration: -1.0,
+        path: String::new(),
+        lyrics: String::from("no lyrics"),
+        hash: String::new(),
+        size: 0,
+        track_gain: None,
+        track_peak: None,
+        album_gain: None,
+        album_peak: None,
+    };
+}
+
+// The same file gives the same path whether it's relative to the working directory or absolute.
+// Files that aren't there anymore are only cleaned up, without following links
+fn normalize(path: &str) -> String {
+    match fs::canonicalize(path) {
+        Ok(p) => { return p.to_string_lossy().to_string(); },
+        Err(_) => {},
+    }
+    let absolute = match env::current_dir() {
+        Ok(d) => d.join(path),
+        Err(_) => PathBuf::from(path),
+    };
+    let mut clean = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => { clean.pop(); },
+            c => clean.push(c),
+        }
+    }
+    return clean.to_string_lossy().to_string();
+}
+
+// The paths a file could be stored under. The database has them the way they were given when the
+// library was scanned, relative to the working directory usually, playlists have them absolute
+fn stored_paths(path: &str) -> Vec<String> {
+    let absolute = normalize(path);
+    let mut paths = vec![String::from(path), absolute.clone()];
+    match env::current_dir() {
+        Ok(d) => {
+            match Path::new(&absolute).strip_prefix(&d) {
+                Ok(relative) => {
+                    let relative = relative.to_string_lossy().to_string();
+                    paths.push(format!("./{}", relative));
+                    paths.push(relative);
+                },
+                Err(_) => {},
+            }
+        },
+        Err(_) => {},
+    }
+    paths.dedup();
+    return paths;
+}
+
+fn path_to_uri(path: &str) -> String {
+    let absolute = normalize(path);
+    let mut uri = String::from("file://");
+    for byte in absolute.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    return uri;
+}
+
+// only local files are supported. Plain paths are accepted too since some players write those
+fn uri_to_path(uri: &str) -> Option<String> {
+    let encoded = match uri.strip_prefix("file://") {
+        Some(v) => v,
+        None => {
+            if uri.contains("://") { return None; }
+            uri
+        },
+    };
+    let bytes = encoded.as_bytes();
+    let mut decoded : Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = String::from_utf8_lossy(&bytes[i+1..i+3]);
+            match u8::from_str_radix(&hex, 16) {
+                Ok(v) => { decoded.push(v); i += 3; continue; },
+                Err(_) => {},
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    return String::from_utf8(decoded).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::songdb;
+
+    fn song(title: &str, album: &str, path: &str) -> Song {
+        let mut song = empty_song();
+        song.title = String::from(title);
+        song.album = String::from(album);
+        song.artist = String::from("artist");
+        song.album_artist = String::from("artist");
+        song.path = String::from(path);
+        return song;
+    }
+
+    fn import_str(db: &SongDB, name: &str, tracks: &str) -> Vec<String> {
+        let path = env::temp_dir().join(format!("rumu-test-{}.xspf", name));
+        let content = format!("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\"><trackList>{}</trackList></playlist>", tracks);
+        fs::write(&path, content).unwrap();
+        let songs = import(db, path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).ok();
+        return songs.into_iter().map(|s| s.path).collect();
+    }
+
+    #[test]
+    fn absolute_locations_find_relative_paths() {
+        let db = songdb::open(":memory:").unwrap();
+        db.add(&song("intro", "first", "music/first/intro.ogg")).unwrap();
+        let location = path_to_uri("music/first/intro.ogg");
+        let tracks = format!("<track><location>{}</location></track>", location);
+        assert_eq!(import_str(&db, "location", &tracks), vec!["music/first/intro.ogg"]);
+    }
+
+    #[test]
+    fn locations_find_dot_relative_paths() {
+        let db = songdb::open(":memory:").unwrap();
+        db.add(&song("intro", "first", "./music/first/intro.ogg")).unwrap();
+        let location = path_to_uri("music/first/intro.ogg");
+        let tracks = format!("<track><location>{}</location></track>", location);
+        assert_eq!(import_str(&db, "dot", &tracks), vec!["./music/first/intro.ogg"]);
+    }
+
+    #[test]
+    fn title_alone_isnt_enough() {
+        let db = songdb::open(":memory:").unwrap();
+        db.add(&song("intro", "first", "music/first/intro.ogg")).unwrap();
+        assert_eq!(import_str(&db, "title", "<track><title>intro</title></track>").len(), 0);
+        let tracks = "<track><title>intro</title><album>first</album></track>";
+        assert_eq!(import_str(&db, "album", tracks), vec!["music/first/intro.ogg"]);
+    }
+}